@@ -0,0 +1,413 @@
+use std::env::{set_var, var};
+use std::fmt::{Display, Formatter};
+
+/// Operators recognized inside an arithmetic expression, longest first so
+/// that `**=` is matched before `**` and `*`.
+const OPERATORS: [&str; 33] = [
+    "**=", "<<=", ">>=",
+    "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=",
+    "+", "-", "*", "/", "%", "<", ">", "!", "~", "&", "|", "^", "=",
+];
+
+const ASSIGNMENTS: [&str; 12] = ["=", "+=", "-=", "*=", "/=", "%=", "**=", "<<=", ">>=", "&=", "|=", "^="];
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Op(o) => write!(f, "{}", o),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let bytes = expr.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i] as char;
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < bytes.len() && (bytes[i] as char).is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let literal = &expr[start..i];
+            let parsed = if let Some(hex) = literal.strip_prefix("0x").or_else(|| literal.strip_prefix("0X")) {
+                i64::from_str_radix(hex, 16)
+            } else {
+                literal.parse::<i64>()
+            };
+            tokens.push(Token::Num(parsed.map_err(|_| format!("invalid number `{}`", literal))?));
+        } else if c == '$' || c.is_ascii_alphabetic() || c == '_' {
+            // `$name` and `${name}` are accepted as well as bare names.
+            let braced = c == '$' && bytes.get(i + 1) == Some(&b'{');
+            if c == '$' {
+                i += if braced { 2 } else { 1 };
+            }
+            let start = i;
+            while i < bytes.len() && ((bytes[i] as char).is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            if start == i {
+                return Err("expected a variable name after `$`".to_string());
+            }
+            let name = expr[start..i].to_string();
+            if braced {
+                if bytes.get(i) != Some(&b'}') {
+                    return Err(format!("missing `}}` after `${{{}`", name));
+                }
+                i += 1;
+            }
+            tokens.push(Token::Ident(name));
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if let Some(op) = OPERATORS.iter().find(|x| expr[i..].starts_with(**x)) {
+            tokens.push(Token::Op(op));
+            i += op.len();
+        } else {
+            return Err(format!("unexpected character `{}`", c));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Binding power of a binary operator, and whether it is right-associative.
+fn precedence(op: &str) -> Option<(u8, bool)> {
+    match op {
+        "||" => Some((1, false)),
+        "&&" => Some((2, false)),
+        "|" => Some((3, false)),
+        "^" => Some((4, false)),
+        "&" => Some((5, false)),
+        "==" | "!=" => Some((6, false)),
+        "<" | "<=" | ">" | ">=" => Some((7, false)),
+        "<<" | ">>" => Some((8, false)),
+        "+" | "-" => Some((9, false)),
+        "*" | "/" | "%" => Some((10, false)),
+        "**" => Some((11, true)),
+        _ => None,
+    }
+}
+
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+    Ok(match op {
+        "||" => (lhs != 0 || rhs != 0) as i64,
+        "&&" => (lhs != 0 && rhs != 0) as i64,
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "&" => lhs & rhs,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "<" => (lhs < rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => return Err("division by zero".to_string()),
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        "**" => {
+            let exp = u32::try_from(rhs).map_err(|_| format!("invalid exponent {}", rhs))?;
+            lhs.wrapping_pow(exp)
+        }
+        _ => return Err(format!("unknown operator `{}`", op)),
+    })
+}
+
+/// Reads a variable from the environment. Unset and empty variables are 0.
+fn lookup(name: &str) -> Result<i64, String> {
+    let value = var(name).unwrap_or_default();
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(0);
+    }
+    value.parse::<i64>().map_err(|_| format!("{}: `{}` is not an integer", name, value))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Above 0 while reading the right side of a `&&` or `||` that the left
+    /// side has already decided, which is parsed but not evaluated.
+    skip: u32,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn assignment(&mut self) -> Result<i64, String> {
+        if let (Some(Token::Ident(name)), Some(Token::Op(op))) = (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            if ASSIGNMENTS.contains(op) {
+                let (name, op) = (name.clone(), *op);
+                self.pos += 2;
+                let rhs = self.assignment()?;
+                if self.skip > 0 {
+                    return Ok(0);
+                }
+                let value = match op.strip_suffix('=').unwrap_or("") {
+                    "" => rhs,
+                    binary => apply(binary, lookup(&name)?, rhs)?,
+                };
+                set_var(&name, value.to_string());
+                return Ok(value);
+            }
+        }
+        self.binary(0)
+    }
+
+    fn binary(&mut self, min: u8) -> Result<i64, String> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            let (prec, right) = match precedence(op) {
+                Some(x) if x.0 >= min => x,
+                _ => break,
+            };
+            self.pos += 1;
+            let decided = (op == "&&" && lhs == 0) || (op == "||" && lhs != 0);
+            if decided {
+                self.skip += 1;
+            }
+            let rhs = self.binary(if right { prec } else { prec + 1 });
+            if decided {
+                self.skip -= 1;
+            }
+            let rhs = rhs?;
+            if self.skip == 0 {
+                lhs = apply(op, lhs, rhs)?;
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.peek() {
+            Some(Token::Op("-")) => {
+                self.pos += 1;
+                Ok(self.unary()?.wrapping_neg())
+            }
+            Some(Token::Op("+")) => {
+                self.pos += 1;
+                self.unary()
+            }
+            Some(Token::Op("!")) => {
+                self.pos += 1;
+                Ok((self.unary()? == 0) as i64)
+            }
+            Some(Token::Op("~")) => {
+                self.pos += 1;
+                Ok(!self.unary()?)
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(n),
+            Some(Token::Ident(_)) if self.skip > 0 => Ok(0),
+            Some(Token::Ident(name)) => lookup(&name),
+            Some(Token::LParen) => {
+                let value = self.assignment()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(value),
+                    Some(t) => Err(format!("expected `)` but found `{}`", t)),
+                    None => Err("missing `)`".to_string()),
+                }
+            }
+            Some(t) => Err(format!("syntax error near `{}`", t)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// Evaluates an integer expression. Variables are read from (and assigned
+/// to) the environment.
+pub fn eval(expr: &str) -> Result<i64, String> {
+    let tokens = tokenize(expr)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = Parser { tokens, pos: 0, skip: 0 };
+    let value = parser.assignment()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(t) => Err(format!("syntax error near `{}`", t)),
+    }
+}
+
+/// Replaces every `$(( ... ))` in the line with the value of the expression.
+pub fn expand_arithmetic(line: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("$((") {
+        out.push_str(&rest[..start]);
+        let body = &rest[start + 3..];
+        let mut depth = 2;
+        let mut end = None;
+        for (i, c) in body.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    // the expression ends with `))`, not two separate `)`
+                    if depth == 1 && body[i + 1..].starts_with(')') {
+                        end = Some(i);
+                        break;
+                    }
+                    if depth == 0 {
+                        return Err("expected `))` to end `$((`".to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        let end = end.ok_or_else(|| "unterminated `$((`".to_string())?;
+        let expr = expand_arithmetic(&body[..end])?;
+        out.push_str(&eval(&expr)?.to_string());
+        rest = &body[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::{set_var, var};
+    use super::{eval, expand_arithmetic};
+
+    #[test]
+    fn numbers() {
+        assert_eq!(eval("42"), Ok(42));
+        assert_eq!(eval("0x1f"), Ok(31));
+        assert_eq!(eval("  7  "), Ok(7));
+        assert_eq!(eval(""), Ok(0));
+        assert!(eval("12ab").is_err());
+        assert!(eval("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("100 / 10 / 5"), Ok(2));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("-2 ** 2"), Ok(4));
+        assert_eq!(eval("1 + 2 == 3"), Ok(1));
+        assert_eq!(eval("1 << 2 + 1"), Ok(8));
+        assert_eq!(eval("6 & 3 | 8"), Ok(10));
+        assert_eq!(eval("1 || 0 && 0"), Ok(1));
+        assert_eq!(eval("!0 + ~0"), Ok(0));
+        assert_eq!(eval("7 % 3 * 2"), Ok(2));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(eval("3 < 4"), Ok(1));
+        assert_eq!(eval("3 >= 4"), Ok(0));
+        assert_eq!(eval("3 != 4"), Ok(1));
+        assert_eq!(eval("!(3 == 3)"), Ok(0));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("1 / 0"), Err("division by zero".to_string()));
+        assert_eq!(eval("1 % (2 - 2)"), Err("division by zero".to_string()));
+    }
+
+    #[test]
+    fn overflow_wraps() {
+        assert_eq!(eval("9223372036854775807 + 1"), Ok(i64::MIN));
+        assert_eq!(eval("-9223372036854775807 - 2"), Ok(i64::MAX));
+        assert_eq!(eval("(-9223372036854775807 - 1) / -1"), Ok(i64::MIN));
+        assert_eq!(eval("2 ** 64"), Ok(0));
+        assert!(eval("2 ** -1").is_err());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(eval("1 +").is_err());
+        assert!(eval("(1 + 2").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("1 @ 2").is_err());
+        assert!(eval("${COSH_TEST_ARITH").is_err());
+    }
+
+    #[test]
+    fn variables() {
+        set_var("COSH_TEST_ARITH_A", "5");
+        assert_eq!(eval("COSH_TEST_ARITH_A * 2"), Ok(10));
+        assert_eq!(eval("$COSH_TEST_ARITH_A + ${COSH_TEST_ARITH_A}"), Ok(10));
+        assert_eq!(eval("COSH_TEST_ARITH_UNSET + 1"), Ok(1));
+        set_var("COSH_TEST_ARITH_B", "abc");
+        assert!(eval("COSH_TEST_ARITH_B").is_err());
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(eval("COSH_TEST_ARITH_C = 3"), Ok(3));
+        assert_eq!(eval("COSH_TEST_ARITH_C += 4"), Ok(7));
+        assert_eq!(eval("COSH_TEST_ARITH_C **= 2"), Ok(49));
+        assert_eq!(var("COSH_TEST_ARITH_C").unwrap(), "49");
+        assert_eq!(eval("COSH_TEST_ARITH_D = COSH_TEST_ARITH_E = 2"), Ok(2));
+        assert_eq!(var("COSH_TEST_ARITH_D").unwrap(), "2");
+        assert!(eval("COSH_TEST_ARITH_F /= 0").is_err());
+    }
+
+    #[test]
+    fn expansion() {
+        assert_eq!(expand_arithmetic("a $((1 + 2)) b $((3 * (1 + 1)))"), Ok("a 3 b 6".to_string()));
+        assert_eq!(expand_arithmetic("$(( $((2)) ** 3 ))"), Ok("8".to_string()));
+        assert_eq!(expand_arithmetic("no expansion"), Ok("no expansion".to_string()));
+        assert!(expand_arithmetic("$((1 + 2)").is_err());
+        assert!(expand_arithmetic("$((1 / 0))").is_err());
+        assert!(expand_arithmetic("$((1)é)").is_err());
+        assert_eq!(expand_arithmetic("é $((1))é"), Ok("é 1é".to_string()));
+    }
+
+    #[test]
+    fn short_circuit() {
+        set_var("COSH_TEST_ARITH_G", "0");
+        assert_eq!(eval("COSH_TEST_ARITH_G != 0 && 10 / COSH_TEST_ARITH_G"), Ok(0));
+        assert_eq!(eval("COSH_TEST_ARITH_G == 0 || 10 / COSH_TEST_ARITH_G"), Ok(1));
+        assert_eq!(eval("0 && (COSH_TEST_ARITH_H = 5)"), Ok(0));
+        assert!(var("COSH_TEST_ARITH_H").is_err());
+        assert_eq!(eval("1 || (COSH_TEST_ARITH_H = 5)"), Ok(1));
+        assert!(var("COSH_TEST_ARITH_H").is_err());
+        assert_eq!(eval("1 && (COSH_TEST_ARITH_H = 5)"), Ok(1));
+        assert_eq!(var("COSH_TEST_ARITH_H").unwrap(), "5");
+        assert!(eval("0 && 1 +").is_err());
+    }
+}
@@ -1,7 +1,10 @@
-use std::env::{split_paths, var_os};
-use std::ffi::OsStr;
-use std::fs::read_dir;
-use std::path::Path;
+use std::env::{current_dir, set_current_dir, set_var, split_paths, var_os};
+use std::fs::{File, read_dir, symlink_metadata};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ExitStatus};
+#[cfg(unix)]
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
 use is_executable::is_executable;
 use yansi::Paint;
 use crate::err_ln;
@@ -24,8 +27,45 @@ fn is_hidden(path: &Path) -> bool {
     path.file_name().unwrap().to_string_lossy().starts_with(".")
 }
 
+/// The working directory. When it has been deleted, this falls back to the
+/// logical `PWD` and the flag is set.
+pub fn working_dir() -> (PathBuf, bool) {
+    match current_dir() {
+        Ok(x) => (x, false),
+        Err(_) => (var_os("PWD").map(PathBuf::from).unwrap_or_default(), true),
+    }
+}
+
+/// Changes the working directory and updates `PWD` and `OLDPWD`. If the
+/// current directory was deleted, paths are resolved against the logical
+/// `PWD` instead, so that `cd ..` still gets out of it.
+pub fn change_dir(dir: &Path) -> std::io::Result<()> {
+    let (old, gone) = working_dir();
+    let mut logical = old.clone();
+    for x in dir.components() {
+        match x {
+            Component::ParentDir => {
+                logical.pop();
+            }
+            Component::CurDir => {}
+            x => logical.push(x),
+        }
+    }
+    if let Err(e) = set_current_dir(dir) {
+        if !gone {
+            return Err(e);
+        }
+        set_current_dir(&logical)?;
+    }
+    set_var("OLDPWD", old);
+    // we may have moved into a deleted parent, which has no path anymore
+    set_var("PWD", current_dir().unwrap_or(logical));
+    Ok(())
+}
+
 /// Prints a listing of the current files. This is equivalent to Windows' `dir`.
-pub fn ls(mut dir: &str, flags: Vec<String>) {
+/// Returns the exit status.
+pub fn ls(mut dir: &str, flags: Vec<String>) -> i32 {
     if dir.is_empty() {
         dir = ".";
     }
@@ -35,12 +75,12 @@ pub fn ls(mut dir: &str, flags: Vec<String>) {
     for x in flags {
         if x != "-l" && x != "-F" && x != "-a" {
             err_ln(format!("unknown option {}", x));
-            return;
+            return 2;
         }
     }
     if !Path::new(dir).exists() {
         err_ln("The system cannot find the file specified.".to_string());
-        return;
+        return 1;
     }
     let mut target = String::new();
     for r in read_dir(dir).unwrap() {
@@ -49,63 +89,250 @@ pub fn ls(mut dir: &str, flags: Vec<String>) {
             if res.is_dir() {
                 target += &*format!("{}", Paint::green(res.file_name().unwrap().to_string_lossy()).bold());
                 if append {
-                    target.push_str(&*Paint::green('/').bold().to_string());
+                    target.push_str(&Paint::green('/').bold().to_string());
                 }
             } else if res.is_symlink() {
                 target += &*format!("{}", Paint::yellow(res.file_name().unwrap().to_string_lossy()).italic());
             } else {
                 target += &*format!("{}", Paint::cyan(res.file_name().unwrap().to_string_lossy()));
                 if is_executable(res.as_path()) {
-                    target.push_str(&*Paint::cyan('*').to_string());
+                    target.push_str(&Paint::cyan('*').to_string());
                 }
             }
             target += " ";
         }
     }
     println!("{}", target.trim());
+    0
 }
 
-/// A completer that will autocomplete file paths and executables
-/// in the `PATH`.
-pub fn autocomplete_targets() -> Vec<String> {
-    let mut autocomplete = vec![];
-    autocomplete.clear();
-    // add built-in commands
-    autocomplete.append(
-        &mut vec![
-            "help"      .to_string(),
-            "history"   .to_string(),
-            "cls"       .to_string(),
-            "pwd"       .to_string(),
-            "echo"      .to_string(),
-            "exit"      .to_string(),
-            "ls"        .to_string(),
-            "cd"        .to_string()
-        ]
-    );
-
-    // PATH var
-    let env = var_os("PATH").unwrap();
-    let paths = split_paths(&env).collect::<Vec<_>>();
-    for p in paths {
-        match read_dir(p) {
-            Ok(r) => {
-                for res in r {
-                    let result = res.unwrap().path();
-                    let result_path = result.as_path();
-                    if !is_hidden(result_path) && is_executable(result_path) {
-                        autocomplete.push(
-                            result.file_name().unwrap().to_string_lossy()
-                                .strip_suffix(&result.extension().unwrap_or(OsStr::new("")).to_string_lossy().to_string())
-                                .unwrap_or(&*result.file_name().unwrap().to_string_lossy())
-                                .to_string()
-                        )
-                    }
-                }
-            }
-            Err(_) => {}
+/// Evaluates a `test` / `[` expression. Returns the exit status: 0 if the
+/// expression is true, 1 if it is false and 2 if it is malformed.
+pub fn test(args: &[String]) -> i32 {
+    let args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+    if args.is_empty() {
+        return 1;
+    }
+    let mut pos = 0;
+    let result = test_or(&args, &mut pos).and_then(|x| {
+        match args.get(pos) {
+            None => Ok(x),
+            Some(extra) => Err(format!("unexpected argument `{}`", extra)),
+        }
+    });
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            err_ln(format!("cosh: test: {}", e));
+            2
+        }
+    }
+}
+
+fn test_or(args: &[&str], pos: &mut usize) -> Result<bool, String> {
+    let mut result = test_and(args, pos)?;
+    while args.get(*pos) == Some(&"-o") {
+        *pos += 1;
+        result |= test_and(args, pos)?;
+    }
+    Ok(result)
+}
+
+fn test_and(args: &[&str], pos: &mut usize) -> Result<bool, String> {
+    let mut result = test_not(args, pos)?;
+    while args.get(*pos) == Some(&"-a") {
+        *pos += 1;
+        result &= test_not(args, pos)?;
+    }
+    Ok(result)
+}
+
+fn test_not(args: &[&str], pos: &mut usize) -> Result<bool, String> {
+    if args.get(*pos) == Some(&"!") && *pos + 1 < args.len() {
+        *pos += 1;
+        return Ok(!test_not(args, pos)?);
+    }
+    test_primary(args, pos)
+}
+
+fn test_primary(args: &[&str], pos: &mut usize) -> Result<bool, String> {
+    let first = *args.get(*pos).ok_or_else(|| "argument expected".to_string())?;
+    // binary operators take priority, so that `[ -f = -f ]` compares strings
+    if let (Some(op), Some(rhs)) = (args.get(*pos + 1), args.get(*pos + 2)) {
+        if let Some(result) = test_binary(first, op, rhs)? {
+            *pos += 3;
+            return Ok(result);
+        }
+    }
+    if first == "(" && args.len() > *pos + 1 {
+        *pos += 1;
+        let result = test_or(args, pos)?;
+        if args.get(*pos) != Some(&")") {
+            return Err("missing `)`".to_string());
+        }
+        *pos += 1;
+        return Ok(result);
+    }
+    if let Some(operand) = args.get(*pos + 1) {
+        if let Some(result) = test_unary(first, operand) {
+            *pos += 2;
+            return Ok(result);
+        }
+    }
+    *pos += 1;
+    Ok(!first.is_empty())
+}
+
+fn test_unary(op: &str, operand: &str) -> Option<bool> {
+    let path = Path::new(operand);
+    Some(match op {
+        "-e" => path.exists(),
+        "-f" => path.is_file(),
+        "-d" => path.is_dir(),
+        "-x" => path.exists() && is_executable(path),
+        "-s" => path.metadata().map(|x| x.len() > 0).unwrap_or(false),
+        "-L" | "-h" => symlink_metadata(path).map(|x| x.file_type().is_symlink()).unwrap_or(false),
+        "-r" => path.is_dir() || File::open(path).is_ok(),
+        "-w" => path.metadata().map(|x| !x.permissions().readonly()).unwrap_or(false),
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        _ => return None,
+    })
+}
+
+fn test_binary(lhs: &str, op: &str, rhs: &str) -> Result<Option<bool>, String> {
+    let int = |x: &str| x.trim().parse::<i64>().map_err(|_| format!("integer expression expected, got `{}`", x));
+    Ok(Some(match op {
+        "=" | "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        ">" => lhs > rhs,
+        "-eq" => int(lhs)? == int(rhs)?,
+        "-ne" => int(lhs)? != int(rhs)?,
+        "-lt" => int(lhs)? < int(rhs)?,
+        "-le" => int(lhs)? <= int(rhs)?,
+        "-gt" => int(lhs)? > int(rhs)?,
+        "-ge" => int(lhs)? >= int(rhs)?,
+        _ => return Ok(None),
+    }))
+}
+
+/// User and system CPU time of every command cosh has waited for, as
+/// reported by `wait4`. Time spent in cosh itself is left out.
+#[cfg(unix)]
+static CHILD_TIMES: Mutex<(Duration, Duration)> = Mutex::new((Duration::ZERO, Duration::ZERO));
+
+/// Waits for a command to end, adding the CPU time it used to what
+/// [`cpu_times`] reports.
+#[cfg(unix)]
+pub fn wait_child(child: Child) -> std::io::Result<ExitStatus> {
+    use std::os::unix::process::ExitStatusExt;
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    while unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) } < 0 {
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e);
         }
     }
-    autocomplete.dedup();
-    autocomplete
-}
\ No newline at end of file
+    let time = |x: libc::timeval| Duration::new(x.tv_sec as u64, x.tv_usec as u32 * 1000);
+    let mut times = CHILD_TIMES.lock().unwrap_or_else(PoisonError::into_inner);
+    times.0 += time(usage.ru_utime);
+    times.1 += time(usage.ru_stime);
+    Ok(ExitStatus::from_raw(status))
+}
+
+#[cfg(windows)]
+pub fn wait_child(mut child: Child) -> std::io::Result<ExitStatus> {
+    child.wait()
+}
+
+/// User and system CPU time used so far by the commands cosh has waited
+/// for, see [`wait_child`].
+#[cfg(unix)]
+pub fn cpu_times() -> Option<(Duration, Duration)> {
+    Some(*CHILD_TIMES.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// User and system CPU time are only reported on unix.
+#[cfg(windows)]
+pub fn cpu_times() -> Option<(Duration, Duration)> {
+    None
+}
+
+/// Prints the times measured by `time`, the way `sh` does.
+pub fn print_times(real: Duration, cpu: Option<(Duration, Duration)>) {
+    let line = |name: &str, x: Duration| err_ln(format!("{}\t{}m{:.3}s", name, x.as_secs() / 60, x.as_secs_f64() % 60.0));
+    err_ln(String::new());
+    line("real", real);
+    if let Some((user, sys)) = cpu {
+        line("user", user);
+        line("sys", sys);
+    }
+}
+
+/// Builtin commands, as offered for completion. The shell runs these, and
+/// only these, itself.
+pub const BUILTINS: [&str; 24] = [
+    "help", "history", "cls", "pwd", "echo", "let", "expr", "test", "[", "source",
+    "export", "unset", "alias", "time", "unalias", "exit", "ls", "cd", "complete",
+    "type", "which", "command", "rehash", "cosh",
+];
+
+/// Builtins that are only kept to say what replaced them.
+const DEPRECATED: [&str; 2] = ["autocp", "autocp-ref"];
+
+/// Whether `name` runs a builtin rather than an external command.
+pub fn is_builtin(name: &str) -> bool {
+    // `.` is `source`, and is left out of completion
+    name == "." || BUILTINS.contains(&name) || DEPRECATED.contains(&name)
+}
+
+/// The executables that `name` can run, in the order `PATH` is searched, so
+/// the first is the one that does. A name with a path in it is only looked
+/// for there.
+pub fn find_executables(name: &str) -> Vec<PathBuf> {
+    if name.contains('/') || (cfg!(windows) && name.contains('\\')) {
+        return executable_at(Path::new(name)).into_iter().collect();
+    }
+    split_paths(&var_os("PATH").unwrap_or_default())
+        .filter_map(|dir| executable_at(&dir.join(name)))
+        .collect()
+}
+
+#[cfg(unix)]
+fn executable_at(path: &Path) -> Option<PathBuf> {
+    (path.is_file() && is_executable(path)).then(|| path.to_path_buf())
+}
+
+#[cfg(windows)]
+fn executable_at(path: &Path) -> Option<PathBuf> {
+    // like starting a process does, `name` stands for `name.exe`
+    let path = match path.extension() {
+        Some(_) => path.to_path_buf(),
+        None => path.with_extension("exe"),
+    };
+    path.is_file().then_some(path)
+}
+
+/// Names of the executables in a directory, without their extension on
+/// Windows.
+pub fn executables(dir: &Path) -> Vec<String> {
+    let entries = match read_dir(dir) {
+        Ok(x) => x,
+        Err(_) => return vec![],
+    };
+    let mut names = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if is_hidden(&path) || !is_executable(&path) {
+            continue;
+        }
+        let name = if cfg!(windows) { path.file_stem() } else { path.file_name() };
+        if let Some(x) = name {
+            names.push(x.to_string_lossy().to_string());
+        }
+    }
+    names
+}
@@ -0,0 +1,322 @@
+use std::env::{split_paths, var_os, vars};
+use std::ffi::OsString;
+use std::fs::{metadata, read_dir};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::SystemTime;
+use reedline::{Completer, Span};
+use crate::builtin::{BUILTINS, executables};
+use crate::compspec::{ArgKind, CompletionSpec, CompletionSpecs};
+use crate::shell::assignment;
+
+/// The command names offered for completion: the builtins, the aliases and
+/// the executables in `PATH`. Shared between the completer, the hinter and the
+/// shell, which asks for a [`refresh`](CommandIndex::refresh) before each
+/// prompt.
+#[derive(Clone, Default)]
+pub struct CommandIndex {
+    state: Arc<Mutex<IndexState>>,
+}
+
+#[derive(Default)]
+struct IndexState {
+    commands: Vec<String>,
+    /// Alias names, which count as commands too.
+    aliases: Vec<String>,
+    /// The `PATH` the commands were read from.
+    path: Option<OsString>,
+    /// Modification times of its directories at the time.
+    mtimes: Vec<Option<SystemTime>>,
+    scanning: bool,
+    /// Set by [`CommandIndex::rescan`] until the next reading starts.
+    rescan: bool,
+}
+
+impl CommandIndex {
+    /// Starts with only the builtins, while `PATH` is read in the
+    /// background.
+    pub fn new() -> CommandIndex {
+        let index = CommandIndex::default();
+        let mut builtins: Vec<String> = BUILTINS.iter().map(|x| x.to_string()).collect();
+        builtins.sort();
+        index.state.lock().unwrap_or_else(PoisonError::into_inner).commands = builtins;
+        index.refresh();
+        index
+    }
+
+    /// Reads `PATH` again if it has changed, if any of its directories has
+    /// been modified since it was read, or if a [`rescan`](CommandIndex::rescan)
+    /// was asked for. This happens on another thread, so slow (network)
+    /// directories never hold up the prompt, and only one runs at a time.
+    pub fn refresh(&self) {
+        let path = var_os("PATH").unwrap_or_default();
+        {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            if state.scanning {
+                return;
+            }
+            state.scanning = true;
+        }
+        let state = self.state.clone();
+        thread::spawn(move || {
+            let dirs: Vec<PathBuf> = split_paths(&path).collect();
+            let mtimes: Vec<_> = dirs.iter()
+                .map(|x| metadata(x).and_then(|x| x.modified()).ok())
+                .collect();
+            let stale = {
+                let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+                // taken now, so a rescan asked for while reading is not lost
+                let rescan = std::mem::take(&mut state.rescan);
+                rescan || state.path.as_ref() != Some(&path) || state.mtimes != mtimes
+            };
+            let commands = stale.then(|| {
+                let mut commands: Vec<String> = BUILTINS.iter().map(|x| x.to_string()).collect();
+                for dir in &dirs {
+                    commands.extend(executables(dir));
+                }
+                commands.sort();
+                commands.dedup();
+                commands
+            });
+            let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(x) = commands {
+                state.commands = x;
+                state.path = Some(path);
+                state.mtimes = mtimes;
+            }
+            state.scanning = false;
+        });
+    }
+
+    /// Makes the next [`refresh`](CommandIndex::refresh) read `PATH` even if
+    /// nothing seems to have changed.
+    pub fn rescan(&self) {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).rescan = true;
+    }
+
+    /// Replaces the alias names.
+    pub fn set_aliases(&self, mut aliases: Vec<String>) {
+        aliases.sort();
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).aliases = aliases;
+    }
+
+    /// Command and alias names starting with `prefix`, in order.
+    pub fn matching(&self, prefix: &str) -> Vec<String> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut names: Vec<String> = state.commands.iter()
+            .chain(&state.aliases)
+            .filter(|x| x.starts_with(prefix))
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Whether `name` is a builtin, an alias or an executable in `PATH`.
+    pub fn contains(&self, name: &str) -> bool {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let find = |x: &Vec<String>| x.binary_search_by(|x| x.as_str().cmp(name)).is_ok();
+        find(&state.commands) || find(&state.aliases)
+    }
+}
+
+/// Completes what the cursor is on, depending on where it is: command names
+/// at the start of a command, variable names after `$`, and the arguments
+/// the command's [`CompletionSpec`] allows (paths, if it has none) anywhere
+/// else.
+pub struct CoshCompleter {
+    commands: CommandIndex,
+    specs: CompletionSpecs,
+}
+
+impl CoshCompleter {
+    pub fn new(commands: CommandIndex, specs: CompletionSpecs) -> CoshCompleter {
+        CoshCompleter { commands, specs }
+    }
+
+    /// What `spec` allows for an argument starting with `word`: flags once a
+    /// `-` is typed, then subcommands and words, then the argument kind.
+    fn arguments(&self, spec: &CompletionSpec, word: &str) -> Vec<String> {
+        let mut candidates = vec![];
+        if word.starts_with('-') {
+            candidates.extend(spec.flags.iter().filter(|x| x.starts_with(word)).cloned());
+        }
+        let mut subcommands: Vec<&String> = spec.subcommands.keys().collect();
+        subcommands.sort();
+        candidates.extend(subcommands.into_iter().chain(&spec.words).filter(|x| x.starts_with(word)).cloned());
+        match spec.args {
+            ArgKind::Path => candidates.extend(complete_paths(word, false)),
+            ArgKind::Dir => candidates.extend(complete_paths(word, true)),
+            ArgKind::Command => candidates.extend(self.commands.matching(word)),
+            ArgKind::None => {}
+        }
+        candidates
+    }
+}
+
+/// Only the completions that extend what was typed, which are all a hint
+/// can show.
+pub struct HintCompleter(pub CoshCompleter);
+
+impl Completer for HintCompleter {
+    fn complete(&self, line: &str, pos: usize) -> Vec<(Span, String)> {
+        self.0.complete(line, pos).into_iter()
+            .filter(|(span, x)| x.starts_with(&line[span.start..span.end]))
+            .collect()
+    }
+}
+
+/// The word the cursor is on, as found by [`context`].
+struct Context {
+    /// Byte offset where the word starts.
+    start: usize,
+    /// The word with quotes and escapes removed.
+    word: String,
+    /// The quote still open at the cursor, if any.
+    quote: Option<char>,
+    /// The words of the command before this one, starting with the command
+    /// name. Empty if the word is the command itself.
+    args: Vec<String>,
+}
+
+/// Finds the word being completed in the line up to the cursor, and the
+/// words of its command before it.
+fn context(line: &str) -> Context {
+    let mut start = 0;
+    let mut word = String::new();
+    let mut quote = None;
+    let mut args: Vec<String> = vec![];
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            word.push(c);
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => word.push(c),
+            (_, '\\') => escaped = true,
+            (Some(_), _) => word.push(c),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, ';' | '|' | '&' | '(') => {
+                args.clear();
+                word.clear();
+                start = i + 1;
+            }
+            (None, _) if c.is_whitespace() => {
+                // leading assignments are left out, so `args` starts at the
+                // command name
+                if !word.is_empty() && (!args.is_empty() || assignment(&word).is_none()) {
+                    args.push(word.clone());
+                }
+                word.clear();
+                start = i + c.len_utf8();
+            }
+            (None, _) => word.push(c),
+        }
+    }
+    Context { start, word, quote, args }
+}
+
+/// Quotes a completed word if it needs it, or if the user opened a quote.
+/// Completing again after the closing quote still works, as the quoted part
+/// and what follows it read as one word.
+fn quote_word(word: &str, quote: Option<char>) -> String {
+    let special = |x: char| x.is_whitespace() || "'\"\\$;|&()<>#*?".contains(x);
+    let quote = match quote {
+        Some(x) => x,
+        None if word.contains(special) => '\'',
+        None => return word.to_string(),
+    };
+    let body = match quote {
+        '"' => word.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "\\$"),
+        _ => word.replace('\'', "'\\''"),
+    };
+    format!("{}{}{}", quote, body, quote)
+}
+
+/// Names of environment variables starting with `prefix`.
+fn complete_vars(prefix: &str) -> Vec<String> {
+    let mut names: Vec<String> = vars()
+        .map(|(name, _)| name)
+        .filter(|x| x.starts_with(prefix))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Entries of the directory `word` points into that complete its last
+/// component, with a `/` after directories. `~/` stands for the home
+/// directory, and hidden files are only offered once a `.` is typed.
+fn complete_paths(word: &str, dirs_only: bool) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(x) => word.split_at(x + 1),
+        None => ("", word),
+    };
+    let search = match dir.strip_prefix("~/") {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => home.join(rest),
+            None => return vec![],
+        },
+        None if dir.is_empty() => PathBuf::from("."),
+        None => PathBuf::from(dir),
+    };
+    let entries = match read_dir(search) {
+        Ok(x) => x,
+        Err(_) => return vec![],
+    };
+    let mut paths = vec![];
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+        // follows symlinks, so a link to a directory counts as one
+        let is_dir = entry.path().is_dir();
+        if dirs_only && !is_dir {
+            continue;
+        }
+        paths.push(format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }));
+    }
+    paths.sort();
+    paths
+}
+
+impl Completer for CoshCompleter {
+    fn complete(&self, line: &str, pos: usize) -> Vec<(Span, String)> {
+        let line = &line[..pos];
+        let context = context(line);
+        let raw = &line[context.start..];
+
+        // `$NAME` and `${NAME`, unless single quoted
+        if context.quote != Some('\'') {
+            if let Some(at) = raw.rfind('$') {
+                let name = raw[at + 1..].trim_start_matches('{');
+                if name.chars().all(|x| x.is_alphanumeric() || x == '_') {
+                    let span = Span::new(pos - name.len(), pos);
+                    return complete_vars(name).into_iter()
+                        .map(|x| (span, x))
+                        .collect();
+                }
+            }
+        }
+
+        let span = Span::new(context.start, pos);
+        let word = &context.word;
+        let candidates = match context.args.split_first() {
+            None if !word.contains('/') => self.commands.matching(word),
+            // a path to a command
+            None => complete_paths(word, false),
+            Some((command, args)) => {
+                let spec = self.specs.lookup(command, args).unwrap_or_default();
+                self.arguments(&spec, word)
+            }
+        };
+        candidates.into_iter()
+            .map(|x| (span, quote_word(&x, context.quote)))
+            .collect()
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fs::{read_dir, read_to_string};
+use std::sync::{Arc, Mutex, PoisonError};
+use serde::Deserialize;
+use crate::config::config_dir;
+use crate::err_ln;
+use crate::shell::quote;
+
+/// What the plain arguments of a command are.
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ArgKind {
+    #[default]
+    Path,
+    Dir,
+    Command,
+    None,
+}
+
+impl ArgKind {
+    fn parse(name: &str) -> Option<ArgKind> {
+        match name {
+            "path" => Some(ArgKind::Path),
+            "dir" => Some(ArgKind::Dir),
+            "command" => Some(ArgKind::Command),
+            "none" => Some(ArgKind::None),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ArgKind::Path => "path",
+            ArgKind::Dir => "dir",
+            ArgKind::Command => "command",
+            ArgKind::None => "none",
+        }
+    }
+}
+
+/// How to complete the arguments of a command, or of one of its
+/// subcommands. Read from `completions/<command>.toml` in the config
+/// directory, or set up with the `complete` builtin.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct CompletionSpec {
+    /// What to offer for arguments that are not flags or subcommands.
+    pub args: ArgKind,
+    /// Offered once a `-` is typed.
+    pub flags: Vec<String>,
+    /// Fixed words offered along with the arguments.
+    pub words: Vec<String>,
+    pub subcommands: HashMap<String, CompletionSpec>,
+}
+
+impl CompletionSpec {
+    /// The spec for what follows `args`, each of which may lead into a
+    /// subcommand.
+    fn resolve(&self, args: &[String]) -> &CompletionSpec {
+        let mut spec = self;
+        for x in args {
+            if let Some(sub) = spec.subcommands.get(x) {
+                spec = sub;
+            }
+        }
+        spec
+    }
+
+    /// Prints `complete` commands that set this spec up again.
+    fn print(&self, path: &str) {
+        let mut line = String::from("complete");
+        if self.args != ArgKind::Path {
+            line += &format!(" -a {}", self.args.name());
+        }
+        if !self.flags.is_empty() {
+            line += &format!(" -f {}", quote(&self.flags.join(" ")));
+        }
+        if !self.words.is_empty() {
+            line += &format!(" -w {}", quote(&self.words.join(" ")));
+        }
+        let mut subcommands: Vec<_> = self.subcommands.keys().cloned().collect();
+        subcommands.sort();
+        if !subcommands.is_empty() {
+            line += &format!(" -s {}", quote(&subcommands.join(" ")));
+        }
+        println!("{} {}", line, path);
+        for x in subcommands {
+            let sub = &self.subcommands[&x];
+            if sub.args != ArgKind::Path || !sub.flags.is_empty() || !sub.words.is_empty() || !sub.subcommands.is_empty() {
+                sub.print(&format!("{} {}", path, x));
+            }
+        }
+    }
+}
+
+/// Completion specs by command name, shared between the shell and the
+/// completer.
+#[derive(Clone, Default)]
+pub struct CompletionSpecs {
+    specs: Arc<Mutex<HashMap<String, CompletionSpec>>>,
+}
+
+impl CompletionSpecs {
+    /// Reads the specs in the `completions` directory of the config
+    /// directory, each named after its command, e.g. `git.toml`. Files that
+    /// do not parse are reported and skipped.
+    pub fn load() -> CompletionSpecs {
+        let mut specs = HashMap::new();
+        specs.insert("cd".to_string(), CompletionSpec { args: ArgKind::Dir, ..CompletionSpec::default() });
+        let dir = config_dir().join("completions");
+        for entry in read_dir(dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            let command = match path.file_stem() {
+                Some(x) if path.extension().is_some_and(|x| x == "toml") => x.to_string_lossy().to_string(),
+                _ => continue,
+            };
+            let parsed = read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|x| toml::from_str::<CompletionSpec>(&x).map_err(|e| e.to_string()));
+            match parsed {
+                Ok(x) => {
+                    specs.insert(command, x);
+                }
+                Err(e) => err_ln(format!("cosh: completions/{}.toml: {}", command, e)),
+            }
+        }
+        CompletionSpecs { specs: Arc::new(Mutex::new(specs)) }
+    }
+
+    /// The spec for what follows `command args...`, if `command` has one.
+    pub fn lookup(&self, command: &str, args: &[String]) -> Option<CompletionSpec> {
+        let specs = self.specs.lock().unwrap_or_else(PoisonError::into_inner);
+        specs.get(command).map(|x| x.resolve(args).clone())
+    }
+}
+
+/// The `complete` builtin:
+///
+/// * `complete` prints every spec, as `complete` commands;
+/// * `complete -r COMMAND` removes the spec of a command;
+/// * `complete [-a KIND] [-f FLAGS] [-w WORDS] [-s SUBCOMMANDS] COMMAND [SUBCOMMAND...]`
+///   adds to the spec of a command or subcommand. `KIND` is `path`, `dir`,
+///   `command` or `none`; the others are lists separated by spaces.
+pub fn complete(specs: &CompletionSpecs, args: &[String]) -> i32 {
+    let mut specs = specs.specs.lock().unwrap_or_else(PoisonError::into_inner);
+    if args.is_empty() {
+        let mut commands: Vec<_> = specs.keys().collect();
+        commands.sort();
+        for x in commands {
+            specs[x].print(x);
+        }
+        return 0;
+    }
+    if args[0] == "-r" {
+        let mut status = 0;
+        for x in &args[1..] {
+            if specs.remove(x).is_none() {
+                err_ln(format!("cosh: complete: {}: no completion spec", x));
+                status = 1;
+            }
+        }
+        return status;
+    }
+
+    let mut kind = None;
+    let mut flags = vec![];
+    let mut words = vec![];
+    let mut subcommands = vec![];
+    let mut rest = args.iter();
+    let mut path = vec![];
+    while let Some(x) = rest.next() {
+        let list = match x.as_str() {
+            "-a" | "-f" | "-w" | "-s" => match rest.next() {
+                Some(value) => value,
+                None => {
+                    err_ln(format!("cosh: complete: {} needs a value", x));
+                    return 2;
+                }
+            },
+            _ => {
+                path.push(x.clone());
+                continue;
+            }
+        };
+        let list = list.split_whitespace().map(|x| x.to_string());
+        match x.as_str() {
+            "-f" => flags.extend(list),
+            "-w" => words.extend(list),
+            "-s" => subcommands.extend(list),
+            _ => match ArgKind::parse(&list.collect::<String>()) {
+                Some(x) => kind = Some(x),
+                None => {
+                    err_ln("cosh: complete: -a takes `path`, `dir`, `command` or `none`".to_string());
+                    return 2;
+                }
+            },
+        }
+    }
+    let (command, subpath) = match path.split_first() {
+        Some(x) => x,
+        None => {
+            err_ln("cosh: complete: command name required".to_string());
+            return 2;
+        }
+    };
+
+    let mut spec = specs.entry(command.clone()).or_default();
+    for x in subpath {
+        spec = spec.subcommands.entry(x.clone()).or_default();
+    }
+    if let Some(x) = kind {
+        spec.args = x;
+    }
+    for (list, new) in [(&mut spec.flags, flags), (&mut spec.words, words)] {
+        for x in new {
+            if !list.contains(&x) {
+                list.push(x);
+            }
+        }
+    }
+    for x in subcommands {
+        spec.subcommands.entry(x).or_default();
+    }
+    0
+}
@@ -1,28 +1,94 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, File, read_to_string};
+use std::io::Write;
 use std::path::PathBuf;
-use std::process::exit;
 use directories::ProjectDirs;
 use serde::{Serialize, Deserialize};
-use crate::{disable_virtual_terminal_processing, err_ln};
+use toml_edit::{DocumentMut, Item, Table, value};
+use crate::err_ln;
 
 #[derive(Serialize)]
 #[derive(Deserialize)]
+#[serde(default)] // -> settings missing from older files keep their defaults.
 pub struct Configuration {
+    // plain values have to come before tables, or TOML serialization fails.
+    pub history_capacity: u32, // -> how many of the latest commands up and down go through.
+    pub history_sync: HistorySync, // -> when commands from other sessions show up in this one.
+    pub history_ignore_dups: bool, // -> don't record a command that repeats the one before it.
+    pub history_ignore_space: bool, // -> don't record commands that start with a space.
+    pub history_ignore: Vec<String>, // -> regular expressions for commands never to record.
+    pub prompt: String, // -> prompt format, see `render_format` in print.rs.
+    pub right_prompt: String, // -> right-aligned on the prompt line, empty for none.
+    pub git_dirty: bool, // -> mark changed files in `{git}`, which stats every tracked file.
+    pub duration_threshold_ms: u64, // -> `{duration}` only shows for commands slower than this.
+    pub highlight: HighlightColors, // -> colors of the command line as it is typed.
+    pub aliases: HashMap<String, String>, // -> aliases by name, as written by `alias --save`.
     pub macros: Option<HashMap<String, String>>, // -> macros come in the form <Original-Command, Alias>.
-    pub history_capacity: u32 // history capacity maximum lines
 }
 
 impl Default for Configuration {
     fn default() -> Self {
         Configuration {
+            history_capacity: 1000,
+            history_sync: HistorySync::Startup,
+            history_ignore_dups: true,
+            history_ignore_space: true,
+            history_ignore: vec![],
+            prompt: DEFAULT_PROMPT.to_string(),
+            right_prompt: String::new(),
+            git_dirty: false,
+            duration_threshold_ms: 2000,
+            highlight: HighlightColors::default(),
+            aliases: HashMap::new(),
             macros: Default::default(),
-            history_capacity: 25
         }
     }
 }
 
+/// When a session takes in the commands other sessions record.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HistorySync {
+    /// Before each prompt, so up goes through every session's commands.
+    Live,
+    /// Only when cosh starts.
+    Startup,
+}
+
+/// Styles of the parts of the command line, each a color name (`red`,
+/// `dark_gray`, `#ff8800`, ...) optionally after `bold`, `dimmed`,
+/// `italic` or `underline`. An empty string leaves that part plain.
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HighlightColors {
+    pub command: String, // -> builtins, aliases and executables.
+    pub unknown: String, // -> commands that would not be found.
+    pub string: String,
+    pub variable: String,
+    pub operator: String, // -> `;`, `&&`, `||`, `|` and `&`.
+    pub redirection: String,
+    pub comment: String,
+}
+
+impl Default for HighlightColors {
+    fn default() -> Self {
+        HighlightColors {
+            command: "green".to_string(),
+            unknown: "red".to_string(),
+            string: "yellow".to_string(),
+            variable: "cyan".to_string(),
+            operator: "purple".to_string(),
+            redirection: "purple".to_string(),
+            comment: "dark_gray".to_string(),
+        }
+    }
+}
+
+/// The prompt cosh has always shown, `cwd user@host`, and how long the last
+/// command took if it was slow.
+pub const DEFAULT_PROMPT: &str = "{yellow}{cwd} {green}{user}@{host} {magenta}{duration}{reset}";
+
 #[inline]
 pub fn config_dir() -> PathBuf {
     ProjectDirs::from("", "", "cosh").unwrap().config_dir().to_path_buf()
@@ -30,9 +96,9 @@ pub fn config_dir() -> PathBuf {
 
 /// Attempts to load the config. If not existing, creates a new one and
 /// returns [`Configuration::default()`].
-#[allow(dead_code)]
 pub fn load_config() -> Configuration {
     if !config_dir().join("cosh.toml").exists() {
+        let _ = create_dir_all(config_dir());
         match File::create(config_dir().join("cosh.toml")) {
             Ok(mut x) => {
                 match x.write_all(toml::to_string_pretty(&Configuration::default()).unwrap().as_bytes()) {
@@ -46,23 +112,38 @@ pub fn load_config() -> Configuration {
             }
             Err(_) => {
                 err_ln(format!("cosh: cannot create cosh.toml in {}", config_dir().to_string_lossy()));
+                return Configuration::default();
             }
         }
     }
-    let mut f = File::open(config_dir().join("cosh.toml")).unwrap();
-    let res: &mut [u8] = &mut [];
-    f.read(res).unwrap();
-    let fin = toml::from_slice::<Configuration>(res);
+    let res = read_to_string(config_dir().join("cosh.toml")).unwrap_or_default();
+    let fin = toml::from_str::<Configuration>(&res);
     match fin {
-        Ok(x) => {
-            return x;
-        }
+        Ok(x) => x,
         Err(e) => {
-            err_ln("cosh: incorrect configuration - delete the file to reset config".to_string());
-            err_ln(format!("cosh: err: {}", e));
-            disable_virtual_terminal_processing();
-            exit(1);
+            // a typo in the config should not lock the user out of the shell
+            err_ln(format!("cosh: incorrect configuration, using the defaults - {}", e));
+            Configuration::default()
         }
     }
+}
 
-}
\ No newline at end of file
+/// Writes the aliases into the `aliases` table of cosh.toml, in place of
+/// both that table and `macros`, so that what is saved is every alias there
+/// is. The rest of the file, comments included, is kept as it was.
+pub fn save_aliases(aliases: &HashMap<String, String>) -> Result<(), String> {
+    let path = config_dir().join("cosh.toml");
+    let mut document = read_to_string(&path).unwrap_or_default()
+        .parse::<DocumentMut>()
+        .map_err(|e| e.to_string())?;
+    let mut table = Table::new();
+    for (name, expansion) in aliases.iter().collect::<BTreeMap<_, _>>() {
+        table.insert(name, value(expansion));
+    }
+    document.remove("macros");
+    document["aliases"] = Item::Table(table);
+    create_dir_all(config_dir()).map_err(|e| e.to_string())?;
+    File::create(&path)
+        .and_then(|mut x| x.write_all(document.to_string().as_bytes()))
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,139 @@
+use std::fs::{read, read_to_string, symlink_metadata};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// What the prompt shows about the repository enclosing a directory.
+#[derive(Clone)]
+pub struct GitStatus {
+    /// Branch name, or `@` and the abbreviated commit when detached.
+    pub head: String,
+    /// Whether tracked files differ from the index. Only set when asked for.
+    pub dirty: bool,
+}
+
+/// Finds the repository enclosing `dir`, returning its work tree and its git
+/// directory. `.git` may be a directory or, for worktrees and submodules, a
+/// file holding `gitdir: <path>`.
+pub fn find_repo(dir: &Path) -> Option<(PathBuf, PathBuf)> {
+    for x in dir.ancestors() {
+        let dot_git = x.join(".git");
+        if dot_git.is_dir() {
+            return Some((x.to_path_buf(), dot_git));
+        }
+        if dot_git.is_file() {
+            let contents = read_to_string(&dot_git).ok()?;
+            let git_dir = x.join(contents.trim().strip_prefix("gitdir:")?.trim());
+            return Some((x.to_path_buf(), git_dir));
+        }
+    }
+    None
+}
+
+/// Reads HEAD: the branch name, or `@` and the abbreviated commit when the
+/// head is detached.
+pub fn head(git_dir: &Path) -> Option<String> {
+    let head = read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(x) => Some(x.strip_prefix("refs/heads/").unwrap_or(x).to_string()),
+        None => Some(format!("@{}", head.get(..7)?)),
+    }
+}
+
+/// Status of the repository enclosing `dir`, if any. Checking for changes
+/// reads the index and stats every tracked file, but never runs `git`.
+pub fn status(dir: &Path, check_dirty: bool) -> Option<GitStatus> {
+    let (work_tree, git_dir) = find_repo(dir)?;
+    Some(GitStatus {
+        head: head(&git_dir)?,
+        dirty: check_dirty && is_dirty(&work_tree, &git_dir),
+    })
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+/// Whether any tracked file was changed, removed or is in conflict, judged
+/// like git's own quick check: by comparing the size and modification time
+/// recorded in the index (versions 2 to 4) with the file on disk.
+fn is_dirty(work_tree: &Path, git_dir: &Path) -> bool {
+    let index = match read(git_dir.join("index")) {
+        Ok(x) => x,
+        // a fresh repository has no index yet
+        Err(_) => return false,
+    };
+    index_is_dirty(work_tree, &index).unwrap_or(false)
+}
+
+fn index_is_dirty(work_tree: &Path, index: &[u8]) -> Option<bool> {
+    if index.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = u32_at(index, 4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = u32_at(index, 8)?;
+    let mut at = 12;
+    let mut path: Vec<u8> = vec![];
+    for _ in 0..count {
+        let start = at;
+        let mtime = u32_at(index, at + 8)?;
+        let mode = u32_at(index, at + 24)?;
+        let size = u32_at(index, at + 36)?;
+        let flags = u16_at(index, at + 60)?;
+        at += 62;
+        let mut skip = flags & 0x8000 != 0; // assume-valid
+        if version >= 3 && flags & 0x4000 != 0 {
+            skip |= u16_at(index, at)? & 0x4000 != 0; // skip-worktree
+            at += 2;
+        }
+        if version == 4 {
+            // the path is stored as how much to drop from the previous one,
+            // followed by what to append to it
+            let mut strip: usize = 0;
+            loop {
+                let byte = *index.get(at)?;
+                at += 1;
+                strip = (strip << 7) | (byte & 0x7f) as usize;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+                strip += 1;
+            }
+            path.truncate(path.len().checked_sub(strip)?);
+            let end = at + index.get(at..)?.iter().position(|x| *x == 0)?;
+            path.extend_from_slice(&index[at..end]);
+            at = end + 1;
+        } else {
+            let end = at + index.get(at..)?.iter().position(|x| *x == 0)?;
+            path = index[at..end].to_vec();
+            // entries are padded with NULs to a multiple of 8 bytes
+            at = start + ((end - start + 8) & !7);
+        }
+        // entries in stages 1 to 3 are the sides of a merge conflict
+        if (flags >> 12) & 0x3 != 0 {
+            return Some(true);
+        }
+        if skip || mode & 0o170000 == 0o160000 {
+            continue;
+        }
+        let file = work_tree.join(String::from_utf8_lossy(&path).as_ref());
+        let meta = match symlink_metadata(&file) {
+            Ok(x) => x,
+            Err(_) => return Some(true),
+        };
+        let modified = meta.modified().ok()
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .map(|x| x.as_secs() as u32);
+        if meta.len() as u32 != size || modified != Some(mtime) {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
@@ -0,0 +1,277 @@
+use std::path::Path;
+use is_executable::is_executable;
+use nu_ansi_term::{Color, Style};
+use reedline::{Highlighter, StyledText};
+use crate::builtin::is_builtin;
+use crate::completer::CommandIndex;
+use crate::config::HighlightColors;
+use crate::shell::assignment;
+
+/// What a stretch of the command line is.
+#[derive(Clone, Copy, PartialEq)]
+enum Part {
+    Plain,
+    Command,
+    Unknown,
+    String,
+    Variable,
+    Operator,
+    Redirection,
+    Comment,
+}
+
+/// Colors the command line as it is typed: commands by whether they would
+/// be found, strings, variables, operators, redirections and comments.
+pub struct CoshHighlighter {
+    commands: CommandIndex,
+    command: Style,
+    unknown: Style,
+    string: Style,
+    variable: Style,
+    operator: Style,
+    redirection: Style,
+    comment: Style,
+}
+
+/// Reads a color name, `#rrggbb` or a 256-color number.
+fn color(name: &str) -> Option<Color> {
+    if let Some(hex) = name.strip_prefix('#').filter(|x| x.len() == 6) {
+        let channel = |at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok();
+        return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+    if let Ok(x) = name.parse::<u8>() {
+        return Some(Color::Fixed(x));
+    }
+    Some(match name {
+        "black" => Color::Black,
+        "dark_gray" => Color::DarkGray,
+        "red" => Color::Red,
+        "light_red" => Color::LightRed,
+        "green" => Color::Green,
+        "light_green" => Color::LightGreen,
+        "yellow" => Color::Yellow,
+        "light_yellow" => Color::LightYellow,
+        "blue" => Color::Blue,
+        "light_blue" => Color::LightBlue,
+        "purple" | "magenta" => Color::Purple,
+        "light_purple" | "light_magenta" => Color::LightPurple,
+        "cyan" => Color::Cyan,
+        "light_cyan" => Color::LightCyan,
+        "white" => Color::White,
+        "light_gray" => Color::LightGray,
+        _ => return None,
+    })
+}
+
+/// Reads a style from the config, such as `bold red`. Unknown words are
+/// ignored.
+fn style(spec: &str) -> Style {
+    let mut style = Style::new();
+    for x in spec.split_whitespace() {
+        style = match x {
+            "bold" => style.bold(),
+            "dimmed" => style.dimmed(),
+            "italic" => style.italic(),
+            "underline" => style.underline(),
+            _ => match color(x) {
+                Some(c) => style.fg(c),
+                None => style,
+            },
+        };
+    }
+    style
+}
+
+/// Whether the characters end a word outside quotes.
+fn ends_word(c: char) -> bool {
+    c.is_whitespace() || ";|&<>()".contains(c)
+}
+
+/// Where the variable starting with the `$` at `at` ends: `$NAME`, `${...}`,
+/// `$?`, `$$` and `$(...)`, including `$((...))`. Returns `at + 1` if the
+/// `$` starts none of these.
+fn variable_end(chars: &[char], at: usize) -> usize {
+    let mut i = at + 1;
+    match chars.get(i) {
+        Some('{') => {
+            while i < chars.len() && chars[i] != '}' {
+                i += 1;
+            }
+            (i + 1).min(chars.len())
+        }
+        Some('(') => {
+            let mut depth = 0;
+            while i < chars.len() {
+                match chars[i] {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                i += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            i
+        }
+        Some('?' | '$') => i + 1,
+        Some(x) if x.is_alphanumeric() || *x == '_' => {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            i
+        }
+        _ => i,
+    }
+}
+
+/// Splits the line into the parts that are colored differently.
+fn parts(line: &str, is_command: impl Fn(&str) -> bool) -> Vec<(Part, String)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut parts: Vec<(Part, String)> = vec![];
+    let mut push = |part: Part, text: &[char]| {
+        match parts.last_mut() {
+            Some((last, x)) if *last == part => x.extend(text),
+            _ => parts.push((part, text.iter().collect())),
+        }
+    };
+    let mut command_position = true;
+    let mut word_start = true;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if command_position && word_start && !ends_word(c) {
+            let end = (i..chars.len()).find(|x| ends_word(chars[*x])).unwrap_or(chars.len());
+            let word: String = chars[i..end].iter().collect();
+            if !word.contains(['\'', '"', '$', '\\', '=', '#']) {
+                push(if is_command(&word) { Part::Command } else { Part::Unknown }, &chars[i..end]);
+                // `time` runs the command that follows it
+                command_position = word == "time";
+                word_start = false;
+                i = end;
+                continue;
+            }
+            // the command, if any, comes after the assignments
+            command_position = assignment(&word).is_some();
+        }
+        let mut start = i;
+        i += 1;
+        let mut next_word_start = false;
+        let part = match c {
+            _ if c.is_whitespace() => {
+                next_word_start = true;
+                if c == '\n' && (start == 0 || chars[start - 1] != '\\') {
+                    command_position = true;
+                }
+                Part::Plain
+            }
+            '#' if word_start => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                Part::Comment
+            }
+            '\\' => {
+                i = (i + 1).min(chars.len());
+                Part::Plain
+            }
+            '\'' => {
+                while i < chars.len() && chars[i] != '\'' {
+                    i += 1;
+                }
+                i = (i + 1).min(chars.len());
+                Part::String
+            }
+            '"' => {
+                // variables inside double quotes keep their own color
+                loop {
+                    match chars.get(i) {
+                        None => break,
+                        Some('"') => {
+                            i += 1;
+                            break;
+                        }
+                        Some('\\') => i = (i + 2).min(chars.len()),
+                        Some('$') if variable_end(&chars, i) > i + 1 => {
+                            push(Part::String, &chars[start..i]);
+                            let end = variable_end(&chars, i);
+                            push(Part::Variable, &chars[i..end]);
+                            start = end;
+                            i = end;
+                        }
+                        Some(_) => i += 1,
+                    }
+                }
+                Part::String
+            }
+            '$' => {
+                i = variable_end(&chars, start);
+                if i > start + 1 { Part::Variable } else { Part::Plain }
+            }
+            ';' | '|' | '&' | '(' | ')' => {
+                if (c == '|' || c == '&') && chars.get(i) == Some(&c) {
+                    i += 1;
+                }
+                next_word_start = true;
+                command_position = c != ')';
+                Part::Operator
+            }
+            '<' | '>' => {
+                while i < chars.len() && "<>&".contains(chars[i]) {
+                    i += 1;
+                }
+                next_word_start = true;
+                Part::Redirection
+            }
+            _ => Part::Plain,
+        };
+        push(part, &chars[start..i]);
+        word_start = next_word_start;
+    }
+    parts
+}
+
+impl CoshHighlighter {
+    pub fn new(commands: CommandIndex, colors: &HighlightColors) -> CoshHighlighter {
+        CoshHighlighter {
+            commands,
+            command: style(&colors.command),
+            unknown: style(&colors.unknown),
+            string: style(&colors.string),
+            variable: style(&colors.variable),
+            operator: style(&colors.operator),
+            redirection: style(&colors.redirection),
+            comment: style(&colors.comment),
+        }
+    }
+
+    /// Whether the command would run: a builtin, an alias, an executable in
+    /// `PATH`, or a path to an executable.
+    fn is_command(&self, name: &str) -> bool {
+        if name.contains('/') {
+            let path = Path::new(name);
+            return path.is_file() && is_executable(path);
+        }
+        is_builtin(name) || self.commands.contains(name)
+    }
+}
+
+impl Highlighter for CoshHighlighter {
+    fn highlight(&self, line: &str) -> StyledText {
+        let mut text = StyledText::new();
+        for (part, x) in parts(line, |x| self.is_command(x)) {
+            let style = match part {
+                Part::Plain => Style::new(),
+                Part::Command => self.command,
+                Part::Unknown => self.unknown,
+                Part::String => self.string,
+                Part::Variable => self.variable,
+                Part::Operator => self.operator,
+                Part::Redirection => self.redirection,
+                Part::Comment => self.comment,
+            };
+            text.push((style, x));
+        }
+        text
+    }
+}
@@ -0,0 +1,78 @@
+use std::env::current_dir;
+use std::sync::{Arc, Mutex, PoisonError};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use nu_ansi_term::Style;
+use reedline::{Completer, EditCommand, EditMode, Emacs, Hinter, History, PromptEditMode, ReedlineEvent};
+use crate::completer::HintCompleter;
+use crate::history::HistoryLog;
+
+/// Suggests how the line could go on, like fish: from history (see
+/// [`HistoryLog::suggest`]), then from the completer. Hints only show with
+/// the cursor at the end of the line, and right-arrow accepts them (see
+/// [`CoshEditMode`]).
+pub struct CoshHinter {
+    log: HistoryLog,
+    completer: HintCompleter,
+    style: Style,
+    /// The hint on screen, for [`CoshEditMode`] to insert.
+    hint: Arc<Mutex<String>>,
+}
+
+impl CoshHinter {
+    pub fn new(log: HistoryLog, completer: HintCompleter, style: Style, hint: Arc<Mutex<String>>) -> CoshHinter {
+        CoshHinter { log, completer, style, hint }
+    }
+
+    fn suggest(&self, line: &str) -> Option<String> {
+        let cwd = current_dir().unwrap_or_default();
+        if let Some(x) = self.log.suggest(line, &cwd) {
+            return Some(x[line.len()..].to_string());
+        }
+        let (span, completion) = self.completer.complete(line, line.len()).into_iter().next()?;
+        Some(completion[span.end - span.start..].to_string())
+    }
+}
+
+impl Hinter for CoshHinter {
+    fn handle(&mut self, line: &str, pos: usize, _history: &dyn History) -> String {
+        let hint = if line.trim().is_empty() || pos != line.len() {
+            String::new()
+        } else {
+            // a hint over several lines could not be drawn after the cursor
+            self.suggest(line).filter(|x| !x.contains('\n')).unwrap_or_default()
+        };
+        let painted = self.style.paint(&hint).to_string();
+        *self.hint.lock().unwrap_or_else(PoisonError::into_inner) = hint;
+        painted
+    }
+}
+
+/// Emacs editing, where right-arrow at the end of the line accepts the
+/// hint shown by [`CoshHinter`].
+pub struct CoshEditMode {
+    emacs: Emacs,
+    hint: Arc<Mutex<String>>,
+}
+
+impl CoshEditMode {
+    pub fn new(hint: Arc<Mutex<String>>) -> CoshEditMode {
+        CoshEditMode { emacs: Emacs::default(), hint }
+    }
+}
+
+impl EditMode for CoshEditMode {
+    fn parse_event(&mut self, event: Event) -> ReedlineEvent {
+        if let Event::Key(KeyEvent { code: KeyCode::Right, modifiers: KeyModifiers::NONE }) = event {
+            // there is only a hint with the cursor at the end of the line
+            let hint = self.hint.lock().unwrap_or_else(PoisonError::into_inner);
+            if !hint.is_empty() {
+                return ReedlineEvent::Edit(hint.chars().map(EditCommand::InsertChar).collect());
+            }
+        }
+        self.emacs.parse_event(event)
+    }
+
+    fn edit_mode(&self) -> PromptEditMode {
+        self.emacs.edit_mode()
+    }
+}
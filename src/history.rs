@@ -0,0 +1,395 @@
+use std::fs::{File, OpenOptions, create_dir_all, read_to_string};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use chrono::Utc;
+use regex::Regex;
+use reedline::{FileBackedHistory, History};
+use serde::{Deserialize, Serialize};
+use crate::config::{Configuration, HistorySync};
+use crate::err_ln;
+
+/// A command that was run, where it ran and how it ended. Entries migrated
+/// from `.cosh-history` only have the command.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub command: String,
+    /// When the command started, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<i32>,
+    /// The session that ran it, see [`HistoryLog::session`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+}
+
+impl Entry {
+    /// An entry with only the command known.
+    pub fn bare(command: String) -> Entry {
+        Entry { command, time: None, cwd: None, duration_ms: None, status: None, session: None }
+    }
+}
+
+/// The history of every session, shared between the shell, which records
+/// commands, and the hinter. It is kept in `history.jsonl`: one JSON entry
+/// per line, appended to by every running session.
+#[derive(Clone)]
+pub struct HistoryLog {
+    state: Arc<Mutex<LogState>>,
+}
+
+struct LogState {
+    path: PathBuf,
+    entries: Vec<Entry>,
+    /// How much of the file has been read into `entries`.
+    offset: u64,
+    /// Identifies this session's entries: when it started, and its process.
+    session: String,
+    sync: HistorySync,
+    ignore_dups: bool,
+    ignore_space: bool,
+    /// Commands matching any of these are never recorded.
+    ignore: Vec<Regex>,
+    /// Off in private mode, and after `history off`.
+    recording: bool,
+}
+
+impl LogState {
+    fn keeps(&self, command: &str) -> bool {
+        self.recording
+            && !(self.ignore_space && command.starts_with(' '))
+            && !self.ignore.iter().any(|x| x.is_match(command))
+    }
+}
+
+/// Opens a history file for reading and writing, creating it if needed,
+/// and waits until no other session has it locked. It stays locked until
+/// it is closed.
+fn open_locked(path: &Path) -> std::io::Result<File> {
+    if let Some(x) = path.parent() {
+        create_dir_all(x)?;
+    }
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+    lock(&file)?;
+    Ok(file)
+}
+
+#[cfg(unix)]
+fn lock(file: &File) -> std::io::Result<()> {
+
+    use std::os::unix::io::AsRawFd;
+    match unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+
+}
+
+#[cfg(windows)]
+fn lock(file: &File) -> std::io::Result<()> {
+
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::LockFileEx;
+    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, OVERLAPPED};
+
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    match unsafe { LockFileEx(file.as_raw_handle() as _, LOCKFILE_EXCLUSIVE_LOCK, 0, !0, !0, &mut overlapped) } {
+        0 => Err(std::io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+
+}
+
+/// Reads the entries of a history file from `offset` on, returning them
+/// and the offset after the last whole line. Lines that do not parse are
+/// skipped, and a last line without its newline is left for later, as
+/// another session may still be writing it.
+fn read_entries(file: &mut File, offset: u64) -> std::io::Result<(Vec<Entry>, u64)> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let whole = contents.rfind('\n').map_or(0, |x| x + 1);
+    let entries = contents[..whole].lines()
+        .filter_map(|x| serde_json::from_str(x).ok())
+        .collect();
+    Ok((entries, offset + whole as u64))
+}
+
+/// Writes entries at the end of a history file, returning where it ends.
+fn write_entries(file: &mut File, entries: &[Entry]) -> std::io::Result<u64> {
+    let mut lines = String::new();
+    for x in entries {
+        lines += &serde_json::to_string(x)?;
+        lines.push('\n');
+    }
+    if file.seek(SeekFrom::End(0))? > 0 {
+        // a session that crashed halfway through a line left it unfinished
+        let mut last = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            lines.insert(0, '\n');
+        }
+    }
+    // written at once, so a line is never split
+    file.write_all(lines.as_bytes())?;
+    file.seek(SeekFrom::End(0))
+}
+
+impl HistoryLog {
+    /// Opens the history at `path`. If there is none yet, the commands of
+    /// the plain `legacy` history file, if any, are carried over. A private
+    /// session reads the history, but records nothing.
+    pub fn open(path: PathBuf, legacy: &Path, config: &Configuration, private: bool) -> HistoryLog {
+        let fresh = !path.exists();
+        let opened = open_locked(&path).and_then(|mut file| {
+            if fresh && legacy.is_file() && file.metadata()?.len() == 0 {
+                let commands = read_to_string(legacy).unwrap_or_default();
+                let entries: Vec<Entry> = commands.lines()
+                    .filter(|x| !x.trim().is_empty())
+                    .map(|x| Entry::bare(x.to_string()))
+                    .collect();
+                write_entries(&mut file, &entries)?;
+            }
+            read_entries(&mut file, 0)
+        });
+        let (entries, offset) = opened.unwrap_or_else(|e| {
+            err_ln(format!("cosh: could not read {} - {}", path.to_string_lossy(), e));
+            (vec![], 0)
+        });
+        let session = format!("{}-{}", Utc::now().timestamp(), std::process::id());
+        let ignore = config.history_ignore.iter()
+            .filter_map(|x| match Regex::new(x) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    err_ln(format!("cosh: history_ignore: {}", e));
+                    None
+                }
+            })
+            .collect();
+        HistoryLog {
+            state: Arc::new(Mutex::new(LogState {
+                path,
+                entries,
+                offset,
+                session,
+                sync: config.history_sync,
+                ignore_dups: config.history_ignore_dups,
+                ignore_space: config.history_ignore_space,
+                ignore,
+                recording: !private,
+            })),
+        }
+    }
+
+    /// Whether commands are being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).recording
+    }
+
+    /// Turns recording off or back on, for `history off` and `history on`.
+    pub fn set_recording(&self, recording: bool) {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).recording = recording;
+    }
+
+    /// Identifies the entries of this session.
+    pub fn session(&self) -> String {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).session.clone()
+    }
+
+    /// Whether the command may be kept at all: recording is on, and neither
+    /// `history_ignore` nor `history_ignore_space` leave it out. The crash
+    /// report goes by this too.
+    pub fn keeps(&self, command: &str) -> bool {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).keeps(command)
+    }
+
+    /// Adds an entry, and appends it to the history file, unless recording
+    /// is off or the settings say to leave it out. Returns whether it was
+    /// added.
+    pub fn record(&self, entry: Entry) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if !state.keeps(&entry.command) {
+            return false;
+        }
+        if state.ignore_dups && state.entries.last().is_some_and(|x| x.command == entry.command) {
+            return false;
+        }
+        let live = state.sync == HistorySync::Live;
+        let offset = state.offset;
+        let written = open_locked(&state.path).and_then(|mut file| {
+            // what other sessions added since, so they stay in order
+            let others = if live { read_entries(&mut file, offset)?.0 } else { vec![] };
+            let end = write_entries(&mut file, std::slice::from_ref(&entry))?;
+            Ok((others, end))
+        });
+        match written {
+            Ok((others, end)) => {
+                state.entries.extend(others);
+                state.offset = end;
+            }
+            Err(e) => err_ln(format!("cosh: could not write to {} - {}", state.path.to_string_lossy(), e)),
+        }
+        state.entries.push(entry);
+        true
+    }
+
+    /// With `history_sync = "live"`, takes in what other sessions recorded
+    /// since. Returns whether there was anything.
+    pub fn sync(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.sync != HistorySync::Live {
+            return false;
+        }
+        let mut file = match File::open(&state.path) {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+        let len = file.metadata().map(|x| x.len()).unwrap_or(0);
+        if len == state.offset {
+            return false;
+        }
+        if len < state.offset {
+            // another session deleted entries, so it is all read again
+            state.entries.clear();
+            state.offset = 0;
+        }
+        match read_entries(&mut file, state.offset) {
+            Ok((entries, offset)) => {
+                state.entries.extend(entries);
+                state.offset = offset;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Removes every entry, emptying the history file.
+    pub fn clear(&self) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        open_locked(&state.path)?.set_len(0)?;
+        state.entries.clear();
+        state.offset = 0;
+        Ok(())
+    }
+
+    /// Removes the entry numbered `number` (from 1, as `history` shows
+    /// them), rewriting the history file.
+    pub fn delete(&self, number: usize) -> Result<Entry, String> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let entry = match number.checked_sub(1).and_then(|x| state.entries.get(x)) {
+            Some(x) => x.clone(),
+            None => return Err(format!("{}: no such entry", number)),
+        };
+        // rewritten in place rather than replaced, as other sessions may be
+        // waiting for the lock on it
+        let rewritten = open_locked(&state.path).and_then(|mut file| {
+            let (mut entries, _) = read_entries(&mut file, 0)?;
+            if let Some(i) = entries.iter().position(|x| *x == entry) {
+                entries.remove(i);
+            }
+            file.set_len(0)?;
+            let end = write_entries(&mut file, &entries)?;
+            Ok((entries, end))
+        });
+        let (entries, end) = rewritten.map_err(|e| e.to_string())?;
+        if state.sync == HistorySync::Live {
+            state.entries = entries;
+            state.offset = end;
+        } else {
+            state.entries.remove(number - 1);
+        }
+        Ok(entry)
+    }
+
+    /// A history for reedline to go through with up and down, holding the
+    /// latest `capacity` commands.
+    pub fn reedline_history(&self, capacity: usize) -> Box<dyn History> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let capacity = capacity.max(1);
+        let mut history: Box<dyn History> = Box::new(FileBackedHistory::new(capacity));
+        for x in &state.entries[state.entries.len().saturating_sub(capacity)..] {
+            history.append(x.command.clone());
+        }
+        history
+    }
+
+    /// Expands references to earlier commands: `!!` is the last one, `!n`
+    /// the one numbered `n`, `!-n` the one `n` back and `!prefix` the
+    /// latest starting with `prefix`. A `!` in single quotes, after a
+    /// backslash, or followed by a space, `=` or `(` is left alone.
+    pub fn expand(&self, line: &str) -> Result<String, String> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let entries = &state.entries;
+        let mut expanded = String::new();
+        let mut chars = line.chars().peekable();
+        let mut single = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if !single => {
+                    expanded.push(c);
+                    if let Some(x) = chars.next() {
+                        expanded.push(x);
+                    }
+                    continue;
+                }
+                '\'' => single = !single,
+                '!' if !single => {
+                    let mut reference = String::new();
+                    if chars.peek() == Some(&'!') {
+                        chars.next();
+                        reference.push('!');
+                    } else {
+                        while let Some(x) = chars.peek().filter(|x| !x.is_whitespace() && !"=(;|&'\"".contains(**x)) {
+                            reference.push(*x);
+                            chars.next();
+                        }
+                    }
+                    if reference.is_empty() {
+                        expanded.push(c);
+                        continue;
+                    }
+                    let found = match reference.as_str() {
+                        "!" => entries.last(),
+                        x => match x.parse::<i64>() {
+                            Ok(n) if n > 0 => entries.get(n as usize - 1),
+                            Ok(n) if n < 0 => entries.len().checked_sub(n.unsigned_abs() as usize).and_then(|i| entries.get(i)),
+                            Ok(_) => None,
+                            Err(_) => entries.iter().rev().find(|e| e.command.starts_with(x)),
+                        },
+                    };
+                    match found {
+                        Some(x) => expanded += &x.command,
+                        None => return Err(format!("!{}: event not found", reference)),
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            expanded.push(c);
+        }
+        Ok(expanded)
+    }
+
+    /// Every entry, oldest first.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).entries.clone()
+    }
+
+    /// The best command that extends `prefix`: one run in `cwd` that
+    /// succeeded, then one run in `cwd`, then one that succeeded, then any.
+    /// Among equals, the latest wins.
+    pub fn suggest(&self, prefix: &str, cwd: &Path) -> Option<String> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        // the last of equal maximums is returned, which is the latest
+        state.entries.iter()
+            .filter(|x| x.command.len() > prefix.len() && x.command.starts_with(prefix))
+            .max_by_key(|x| (x.cwd.as_deref() == Some(cwd)) as u8 * 2 + (x.status == Some(0)) as u8)
+            .map(|x| x.command.clone())
+    }
+}
@@ -1,27 +1,46 @@
-use std::borrow::BorrowMut;
-use std::env::*;
-use std::fs::File;
-use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::str::SplitWhitespace;
+use std::env::{args, current_dir, set_var};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::Utc;
 use clearscreen::clear;
-use reedline::{DefaultCompleter, DefaultCompletionActionHandler, DefaultHighlighter, DefaultHinter, FileBackedHistory, Reedline, Signal};
+use reedline::{DefaultCompletionActionHandler, Reedline, Signal};
 use std::string::String;
 #[cfg(unix)]
 use libc::{SIG_DFL, SIGINT, SIGQUIT};
 use nu_ansi_term::Color::DarkGray;
-use nu_ansi_term::{Color, Style};
+use nu_ansi_term::Style;
 use print::*;
 use yansi::Paint;
-use crate::builtin::*;
-use crate::config::config_dir;
-use crate::panics::attach_cosh_panic_handler;
+use crate::builtin::{wait_child, working_dir};
+use crate::completer::{CommandIndex, CoshCompleter, HintCompleter};
+use crate::compspec::CompletionSpecs;
+use crate::highlighter::CoshHighlighter;
+use crate::hinter::{CoshEditMode, CoshHinter};
+use crate::history::{Entry, HistoryLog};
+use crate::config::{config_dir, load_config};
+use crate::panics::{attach_cosh_panic_handler, note_command};
+use crate::shell::Shell;
+use crate::validator::CoshValidator;
 
 mod print;
 mod builtin;
 mod config;
 mod permission;
 mod panics;
+mod arith;
+mod parse;
+mod shell;
+mod validator;
+mod git;
+mod completer;
+mod compspec;
+mod highlighter;
+mod hinter;
+mod history;
 
 pub const HEADER: &str = r#"
                   _
@@ -44,123 +63,108 @@ fn main() {
         )
     );
     attach_cosh_panic_handler();
-    // /*FIXME_later*/ let config = load_config();
-    let coshf_history: PathBuf = config_dir().join(".cosh-history");
+    let mut private = false;
+    for x in args().skip(1) {
+        match x.as_str() {
+            "--private" => private = true,
+            _ => err_ln(format!("cosh: {}: unknown option", x)),
+        }
+    }
+    if private {
+        println!("cosh: private session - commands are not recorded in history\n");
+    }
+    let config = load_config();
+    if let Ok(x) = current_dir() {
+        // `PWD` is what the prompt falls back to if the directory is deleted
+        set_var("PWD", x);
+    }
+    let coshf_history: PathBuf = config_dir().join("history.jsonl");
     let history_str = coshf_history.to_string_lossy().to_string().replace("\\", "/");
-    let history = FileBackedHistory::with_file(25, coshf_history).unwrap();
-    let exe_vec = autocomplete_targets();
-    let mut rl = Reedline::create()
+    let log = HistoryLog::open(coshf_history, &config_dir().join(".cosh-history"), &config, private);
+    // reedline keeps its own copy of the latest commands, for up and down
+    let history = log.reedline_history(config.history_capacity as usize);
+    let commands = CommandIndex::new();
+    let specs = CompletionSpecs::load();
+    let hint = Arc::new(Mutex::new(String::new()));
+    let rl = Reedline::create()
         .unwrap()
         .with_completion_action_handler(
-            Box::new(DefaultCompletionActionHandler::default().with_completer(Box::new(DefaultCompleter::new(exe_vec.clone()))))
+            Box::new(DefaultCompletionActionHandler::default().with_completer(Box::new(CoshCompleter::new(commands.clone(), specs.clone()))))
         )
-        .with_history(Box::new(history))
+        .with_history(history)
         .unwrap()
-        .with_hinter(Box::new(
-            DefaultHinter::default().with_inside_line().with_completer(Box::new(DefaultCompleter::new(exe_vec.clone()))).with_style(Style::new().fg(DarkGray))
-        ));
+        .with_hinter(Box::new(CoshHinter::new(
+            log.clone(),
+            HintCompleter(CoshCompleter::new(commands.clone(), specs.clone())),
+            Style::new().fg(DarkGray),
+            hint.clone(),
+        )))
+        .with_edit_mode(Box::new(CoshEditMode::new(hint)))
+        .with_highlighter(Box::new(CoshHighlighter::new(commands.clone(), &config.highlight)))
+        .with_validator(Box::new(CoshValidator));
+
+    let mut prompt = Cosh {
+        format: config.prompt.clone(),
+        right_format: config.right_prompt.clone(),
+        git_dirty: config.git_dirty,
+        duration_threshold: Duration::from_millis(config.duration_threshold_ms),
+        ..Cosh::default()
+    };
+    let mut shell = Shell::new(rl, config, history_str, commands, specs, log);
 
-    loop {
-        let input = rl.read_line(&Cosh::default());
+    let coshrc = config_dir().join("coshrc");
+    if coshrc.is_file() {
+        shell.source(&coshrc);
+    }
+
+    while !shell.exiting {
+        prompt.status = shell.status;
+        if shell.rehash {
+            prompt.refresh_identity();
+            shell.rehash = false;
+        }
+        prompt.refresh();
+        shell.commands.refresh();
+        if shell.log.sync() {
+            shell.reload_history();
+        }
+        shell.commands.set_aliases(shell.aliases.keys().cloned().collect());
+        let input = shell.rl.read_line(&prompt);
         match input {
             Ok(Signal::Success(res)) => {
-                let may_have_comments = res.trim();
-                if may_have_comments.is_empty() {
-                    continue;
-                }
-                let no_comments = may_have_comments.split("#").next().unwrap();
-                let mut parts = no_comments.split_whitespace();
-                let p = parts.next();
-                let command : &str;
-                match p {
-                    None => { continue; }
-                    Some(e) => {
-                        command = e;
-                    }
-                }
-                let args = parts;
-                match command {
-                    "autocp" => {
-                        println!("cosh: use `autocp-ref` to refresh autocompletion indexes.");
-                    }
-                    "autocp-ref" => {
-                        rl = Reedline::create()
-                            .unwrap()
-                            .with_history(Box::new(FileBackedHistory::with_file(25, history_str.parse().unwrap()).unwrap()))
-                            .unwrap()
-                            .with_hinter(Box::new(
-                                DefaultHinter::default().with_inside_line().with_completer(Box::new(DefaultCompleter::new(exe_vec.clone()))).with_style(Style::new().fg(DarkGray))
-                            ));
-                        println!("cosh: refreshed indexes");
-                    }
-                    "pwd" => {
-                        println!("{}", current_dir().unwrap().to_string_lossy());
-                    }
-                    "history" => {
-                        if args.peekable().peek().unwrap_or(&"") == &"clear" {
-                            match File::create(&history_str) {
-                                Ok(_) => {
-                                    println!("cosh: history file ({}) emptied", history_str);
-                                }
-                                Err(e) => {
-                                    err_ln(format!("cosh: could not empty ({})", history_str));
-                                    err_ln(format!("cosh: error - {}", e));
-                                }
-                            }
-                        } else {
-                            println!("cosh: printing history from {}", history_str);
-                            rl.print_history().unwrap();
-                        }
-                    }
-                    "echo" => {
-                        let mut target = String::new();
-                        for x in args {
-                            target.push_str(&*(x.to_owned() + " "));
-                        }
-                        println!("{}", target.trim());
-                    }
-                    "help" => {
-                        print_help();
-                    }
-                    "cosh" => {
-                        err_ln("cosh: if we let you do this, cosh would break :c".to_string());
-                    }
-                    "cd" => {
-                        let new_dir = args.peekable().peek().map_or("/", |x| *x);
-                        let root = Path::new(new_dir);
-                        if let Err(e) = set_current_dir(&root) {
-                            err_ln(format!("cosh: {}", e));
-                        }
-                    },
-                    "cls" => {
-                        clear().unwrap();
-                    }
-                    "ls" => {
-                        let mut dir = String::new();
-                        let mut proc_args = Vec::<String>::new();
-                        for arg in args.peekable().borrow_mut() {
-                            if arg.starts_with("-") {
-                                if arg.starts_with("--") {
-                                    err_ln("cosh: the internal `ls` implementation only recognizes short flags (-l, etc)".to_string());
-                                    continue;
-                                } else {
-                                    proc_args.push(arg.trim().to_owned());
-                                }
-                            } else {
-                                if dir.is_empty() {
-                                    dir = arg.trim().to_owned();
-                                } else {
-                                    err_ln("cosh: `ls` expected one parameter".to_string());
-                                    continue;
-                                }
-                            }
-                        }
-                        ls(&*dir, proc_args);
+                let (res, expanded) = match shell.log.expand(&res) {
+                    Ok(x) if x != res => {
+                        // shown before it runs, and kept in history as it ran
+                        println!("{}", x);
+                        (x, true)
                     }
-                    "exit" => break,
-                    command => {
-                        execute_command(command, args);
+                    Ok(x) => (x, false),
+                    Err(e) => {
+                        err_ln(format!("cosh: {}", e));
+                        shell.status = 1;
+                        continue;
                     }
+                };
+                let cwd = working_dir().0;
+                let time = Utc::now().timestamp();
+                let start = Instant::now();
+                note_command(&res, shell.log.keeps(&res));
+                shell.run_input(&res);
+                let duration = start.elapsed();
+                prompt.duration = Some(duration);
+                let recorded = shell.log.record(Entry {
+                    command: res,
+                    time: Some(time),
+                    cwd: Some(cwd),
+                    duration_ms: Some(duration.as_millis() as u64),
+                    status: Some(shell.status),
+                    session: Some(shell.log.session()),
+                });
+                // reedline remembers every line as it was typed. What was left
+                // out on purpose is dropped from it too, but with recording
+                // off, up and down still go through the session's commands.
+                if expanded || (!recorded && shell.log.is_recording()) {
+                    shell.reload_history();
                 }
             }
             Ok(Signal::CtrlC) | Ok(Signal::CtrlD) => {}
@@ -171,36 +175,67 @@ fn main() {
         }
     }
     disable_virtual_terminal_processing();
+    // history is written as each command finishes, `exit` included, so
+    // only the output is left to flush
+    let _ = std::io::stdout().flush();
+    std::process::exit(shell.status);
+}
+
+/// Starts the command and waits for it, writing `stdin` (if given) to its
+/// standard input.
+fn spawn_and_wait(command: &mut Command, stdin: Option<String>) -> std::io::Result<ExitStatus> {
+    if stdin.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    let mut child = command.spawn()?;
+    if let (Some(text), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // written from another thread, so a child that fills its stdout
+        // before reading everything cannot deadlock us
+        thread::spawn(move || {
+            let _ = pipe.write_all(text.as_bytes());
+        });
+    }
+    wait_child(child)
+}
+
+/// Exit status of a child, or of a command that could not be started.
+fn exit_status(result: std::io::Result<ExitStatus>) -> i32 {
+    match result {
+        Ok(status) => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::process::ExitStatusExt;
+                if let Some(signal) = status.signal() {
+                    return 128 + signal;
+                }
+            }
+            status.code().unwrap_or(1)
+        }
+        Err(e) => {
+            err_ln(format!("cosh: {}", e));
+            if e.kind() == ErrorKind::NotFound { 127 } else { 126 }
+        }
+    }
 }
 
 #[cfg(unix)]
-fn execute_command(command: &str, args: SplitWhitespace) {
+fn execute_command(command: &str, args: &[String], stdin: Option<String>) -> i32 {
     use std::os::unix::process::CommandExt;
-    let child = Command::new(command).args(args).before_exec(|| {
-        unsafe {
+    let mut child = Command::new(command);
+    unsafe {
+        child.args(args).pre_exec(|| {
             libc::signal(SIGINT, SIG_DFL);
             libc::signal(SIGQUIT, SIG_DFL);
-        }
-        Ok(())
-    }).spawn();
-    match child {
-        Ok(mut child) => { child.wait().unwrap(); },
-        Err(e) => {
-            err_ln(format!("cosh: {}", e));
-        },
-    };
+            Ok(())
+        });
+    }
+    exit_status(spawn_and_wait(&mut child, stdin))
 }
 
 
 #[cfg(windows)]
-fn execute_command(command: &str, args: SplitWhitespace) {
-    let child = Command::new(command).args(args).spawn();
-    match child {
-        Ok(mut child) => { child.wait().unwrap(); },
-        Err(e) => {
-            err_ln(format!("cosh: {}", e));
-        },
-    };
+fn execute_command(command: &str, args: &[String], stdin: Option<String>) -> i32 {
+    exit_status(spawn_and_wait(Command::new(command).args(args), stdin))
 }
 
 #[cfg(windows)]
@@ -1,50 +1,199 @@
-use std::env::{current_exe, temp_dir};
-use std::fs::{create_dir, File};
+use std::env::temp_dir;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::{create_dir_all, File};
 use std::{env, panic};
 use std::io::Write;
-use std::panic::PanicInfo;
-use std::path::Path;
-use backtrace::{Backtrace, trace};
-use chrono::Utc;
+use std::panic::{AssertUnwindSafe, PanicHookInfo, catch_unwind};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use backtrace::Backtrace;
+use chrono::Local;
 use clearscreen::clear;
-use dirs::home_dir;
 use guess_host_triple::guess_host_triple;
 use rustc_version_runtime::version;
 use yansi::Paint;
+use crate::config::config_dir;
 use crate::err_ln;
 
+/// How many of the latest commands go into a crash report.
+const CRASH_HISTORY: usize = 20;
+
+/// The latest commands of this session, the last one being the one that
+/// runs now. Those that history leaves out are `None`.
+static SESSION: Mutex<VecDeque<Option<String>>> = Mutex::new(VecDeque::new());
+
+thread_local! {
+    /// How many [`catch_fault`] calls are running on this thread.
+    static GUARDS: Cell<u32> = const { Cell::new(0) };
+    /// What the last caught panic was, for [`catch_fault`] to return.
+    static FAULT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
 pub fn attach_cosh_panic_handler() {
-    panic::set_hook(Box::new(|info| handle_panic(info)));
+    panic::set_hook(Box::new(handle_panic));
+}
+
+/// Runs a command, turning a panic inside it into an error that describes
+/// it, so that the session goes on.
+pub fn catch_fault<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    GUARDS.with(|x| x.set(x.get() + 1));
+    let result = catch_unwind(AssertUnwindSafe(f));
+    GUARDS.with(|x| x.set(x.get() - 1));
+    result.map_err(|_| FAULT.with(|x| x.borrow_mut().take()).unwrap_or_else(|| "unknown fault".to_string()))
+}
+
+/// Notes the command that is about to run, for the crash report. Unless
+/// `recorded`, as in a private session, after `history off` or for a command
+/// the history settings leave out, only the fact that a command ran is kept.
+pub fn note_command(command: &str, recorded: bool) {
+    let mut session = SESSION.lock().unwrap_or_else(PoisonError::into_inner);
+    if session.len() == CRASH_HISTORY {
+        session.pop_front();
+    }
+    session.push_back(recorded.then(|| command.to_string()));
 }
 
-fn handle_panic(info: &PanicInfo) {
+/// The panic message, and where it happened.
+fn describe(info: &PanicHookInfo) -> String {
+    let cause = if let Some(x) = info.payload().downcast_ref::<&str>() {
+        x.to_string()
+    } else if let Some(x) = info.payload().downcast_ref::<String>() {
+        x.clone()
+    } else {
+        "unknown".to_string()
+    };
+    match info.location() {
+        Some(x) => format!("{} ({}:{})", cause, x.file(), x.line()),
+        None => cause,
+    }
+}
+
+fn handle_panic(info: &PanicHookInfo) {
+    // a fault in a command only ends the command, see `catch_fault`
+    if GUARDS.with(|x| x.get()) > 0 {
+        FAULT.with(|x| *x.borrow_mut() = Some(describe(info)));
+        return;
+    }
+    // the session goes on without a background task, such as a PATH scan
+    if thread::current().name() != Some("main") {
+        err_ln(format!("cosh: internal error in a background task: {}", describe(info)));
+        return;
+    }
     #[allow(unused_must_use)]
     clear();
     err_ln("cosh is no longer able to continue execution due to a fault.".to_string());
-    println!("{}", Paint::yellow("\nPanic information:").bold());
+    let report = crash_report(info);
+    match write_report(&report) {
+        Ok(path) => {
+            println!("{}", Paint::yellow("\nA crash report was written to:").bold());
+            println!("{}\n", path.display());
+            println!("Please attach it when reporting the problem. It holds the last commands run, so check it for anything private first.");
+        }
+        Err(e) => {
+            err_ln(format!("cosh: cannot write the crash report: {}", e));
+            println!("{}", report);
+        }
+    }
+}
+
+/// Everything known about a panic: what and where it was, the system, the
+/// backtrace and the latest commands of the session.
+fn crash_report(info: &PanicHookInfo) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "cosh {} crash report, {}", env!("CARGO_PKG_VERSION"), Local::now().to_rfc2822());
+    let _ = writeln!(report, "\nPanic information:");
     if let Some(x) = info.payload().downcast_ref::<&str>() {
-        println!("cause            : {:?}", x)
+        let _ = writeln!(report, "cause            : {:?}", x);
     } else if let Some(x) = info.payload().downcast_ref::<String>() {
-        println!("cause            : {:?}", x)
+        let _ = writeln!(report, "cause            : {:?}", x);
     } else {
-        println!("cause            : unknown");
+        let _ = writeln!(report, "cause            : unknown");
     }
     if let Some(location) = info.location() {
-        println!("location         : file '{}' at {}:{}", location.file(), location.line(), location.column());
+        let _ = writeln!(report, "location         : file '{}' at {}:{}", location.file(), location.line(), location.column());
     } else {
-        println!("location         : cannot get location");
-    }
-    println!("{}", Paint::yellow("\nSystem information:").bold());
-    println!("operating system : {}", env::consts::OS);
-    println!("system family    : {}", env::consts::FAMILY);
-    println!("processor arch   : {}", env::consts::ARCH);
-    println!("rustc version    : {}", version());
-    println!("target triple    : {}\n", guess_host_triple().unwrap_or("unknown"));
-    let backtrace = Backtrace::new();
-    let mut temp: u8 = 0;
-    for f in backtrace.frames() {
-        temp += 1;
-        let symbol = f.symbols().get(0).unwrap();
-        println!("{} - {:#?} ({})", Paint::cyan(format!("frame {}:", temp)), f.ip(), symbol.name().unwrap());
-    }
-}
\ No newline at end of file
+        let _ = writeln!(report, "location         : cannot get location");
+    }
+    let _ = writeln!(report, "\nSystem information:");
+    let _ = writeln!(report, "operating system : {}", env::consts::OS);
+    let _ = writeln!(report, "system family    : {}", env::consts::FAMILY);
+    let _ = writeln!(report, "processor arch   : {}", env::consts::ARCH);
+    let _ = writeln!(report, "rustc version    : {}", version());
+    let _ = writeln!(report, "target triple    : {}", guess_host_triple().unwrap_or("unknown"));
+
+    let _ = writeln!(report, "\nBacktrace:");
+    report += &backtrace();
+
+    let _ = writeln!(report, "\nLast commands of this session, the last one running:");
+    for x in SESSION.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+        let _ = writeln!(report, "{}", x.as_deref().unwrap_or("(not recorded)"));
+    }
+    report
+}
+
+/// Whether a function belongs to the standard library, the panic machinery
+/// or this handler, rather than to what went wrong. The standard library is
+/// also known by its sources, which are under `/rustc/`.
+fn is_internal(name: &str, file: Option<&Path>) -> bool {
+    const PREFIXES: [&str; 10] = [
+        "std::", "core::", "alloc::", "backtrace::", "panic_unwind::", "cosh::panics::",
+        "rust_begin_unwind", "__rust", "_start", "__libc_start",
+    ];
+    PREFIXES.iter().any(|x| name.starts_with(x))
+        || name == "main"
+        || file.is_some_and(|x| x.starts_with("/rustc/"))
+}
+
+/// The frames of the current thread, with their functions and where they
+/// are in the source. Frames without symbols are shown by address only.
+fn backtrace() -> String {
+    let mut out = String::new();
+    let mut number = 0;
+    let mut hidden = 0;
+    for frame in Backtrace::new().frames() {
+        let symbols = frame.symbols();
+        if symbols.is_empty() {
+            number += 1;
+            let _ = writeln!(out, "{:>4}: {:?} <unknown>", number, frame.ip());
+            continue;
+        }
+        // inlined functions share a frame with their caller
+        for symbol in symbols {
+            let name = symbol.name().map_or("<unknown>".to_string(), |x| format!("{:#}", x));
+            if is_internal(&name, symbol.filename()) {
+                hidden += 1;
+                continue;
+            }
+            number += 1;
+            let _ = writeln!(out, "{:>4}: {}", number, name);
+            match (symbol.filename(), symbol.lineno()) {
+                (Some(file), Some(line)) => {
+                    let _ = writeln!(out, "          at {}:{}", file.display(), line);
+                }
+                (Some(file), None) => {
+                    let _ = writeln!(out, "          at {}", file.display());
+                }
+                _ => {}
+            }
+        }
+    }
+    if hidden > 0 {
+        let _ = writeln!(out, "({} frames of the standard library and panic handling left out)", hidden);
+    }
+    out
+}
+
+/// Writes the report to the `crashes` directory of the config directory, or
+/// the temporary directory if that cannot be created, and returns its path.
+fn write_report(report: &str) -> std::io::Result<PathBuf> {
+    let mut dir = config_dir().join("crashes");
+    if create_dir_all(&dir).is_err() {
+        dir = temp_dir();
+    }
+    let path = dir.join(format!("crash-{}-{}.txt", Local::now().format("%Y%m%d-%H%M%S"), std::process::id()));
+    File::create(&path)?.write_all(report.as_bytes())?;
+    Ok(path)
+}
@@ -0,0 +1,687 @@
+use std::collections::VecDeque;
+use std::env::var;
+use std::process;
+use dirs::home_dir;
+use crate::arith;
+
+/// How a command is joined to the one before it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Connector {
+    /// First command of the line, or after `;`.
+    Always,
+    /// After `&&`: runs only if the previous command succeeded.
+    And,
+    /// After `||`: runs only if the previous command failed.
+    Or,
+}
+
+impl Connector {
+    /// Whether the command after this connector is skipped, given the exit
+    /// status of the one before it.
+    pub fn skips(self, status: i32) -> bool {
+        match self {
+            Connector::Always => false,
+            Connector::And => status != 0,
+            Connector::Or => status == 0,
+        }
+    }
+}
+
+/// Splits a line into commands at unquoted `;`, `&&` and `||`, dropping a
+/// trailing `#` comment. Each command is returned unexpanded together with
+/// the connector that precedes it.
+pub fn split_commands(line: &str) -> Result<Vec<(Connector, String)>, String> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut commands = vec![];
+    let mut connector = Connector::Always;
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                current.push(c);
+                i += 1;
+                if i < chars.len() {
+                    current.push(chars[i]);
+                }
+                i += 1;
+                continue;
+            }
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (_, '$') if chars.get(i + 1) == Some(&'(') && chars.get(i + 2) == Some(&'(') => {
+                depth += 2;
+                current.push_str("$((");
+                i += 3;
+                continue;
+            }
+            (_, '(') if depth > 0 => depth += 1,
+            (_, ')') if depth > 0 => depth -= 1,
+            (None, '#') if depth == 0 && (i == 0 || chars[i - 1].is_whitespace()) => break,
+            (None, ';') if depth == 0 => {
+                push_command(&mut commands, connector, &mut current, ";")?;
+                connector = Connector::Always;
+                i += 1;
+                continue;
+            }
+            (None, '&') | (None, '|') if depth == 0 && chars.get(i + 1) == Some(&c) => {
+                let op = if c == '&' { "&&" } else { "||" };
+                push_command(&mut commands, connector, &mut current, op)?;
+                connector = if c == '&' { Connector::And } else { Connector::Or };
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+        i += 1;
+    }
+    if quote.is_some() {
+        return Err("unterminated quote".to_string());
+    }
+    if depth > 0 {
+        return Err("unterminated `$((`".to_string());
+    }
+    if current.trim().is_empty() {
+        if connector != Connector::Always {
+            return Err("syntax error: unexpected end of line".to_string());
+        }
+    } else {
+        commands.push((connector, current.trim().to_string()));
+    }
+    Ok(commands)
+}
+
+fn push_command(commands: &mut Vec<(Connector, String)>, connector: Connector, current: &mut String, op: &str) -> Result<(), String> {
+    if current.trim().is_empty() {
+        return Err(format!("syntax error near `{}`", op));
+    }
+    commands.push((connector, current.trim().to_string()));
+    current.clear();
+    Ok(())
+}
+
+/// Scans the text for what is left open at its end. Returns whether more
+/// input is needed, and whether that is because of a trailing `\`.
+fn open_state(text: &str) -> (bool, bool) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut quote: Option<char> = None;
+    let mut depth = 0;
+    let mut backslash = false;
+    // whether the last thing outside comments is an `&&` or `||`
+    let mut operator = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match (quote, c) {
+            (Some(q), _) if q == c => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                if i + 1 == chars.len() {
+                    backslash = true;
+                    break;
+                }
+                operator = false;
+                i += 2;
+                continue;
+            }
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (_, '$') if chars.get(i + 1) == Some(&'(') && chars.get(i + 2) == Some(&'(') => {
+                depth += 2;
+                i += 3;
+                continue;
+            }
+            (_, '(') if depth > 0 => depth += 1,
+            (_, ')') if depth > 0 => depth -= 1,
+            (None, '#') if depth == 0 && (i == 0 || chars[i - 1].is_whitespace()) => {
+                // the rest of this line is a comment
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            (None, '&') | (None, '|') if depth == 0 && chars.get(i + 1) == Some(&c) => {
+                operator = true;
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            operator = false;
+        }
+        i += 1;
+    }
+    (backslash || quote.is_some() || depth > 0 || operator, backslash)
+}
+
+/// Whether the text cannot be run yet: it ends inside a quote or a `$((`, or
+/// with a `\`, `&&` or `||` asking for another line.
+pub fn incomplete(text: &str) -> bool {
+    open_state(text).0
+}
+
+/// Whether the input needs more lines before it can run: a line of it is
+/// [`incomplete`], or a here-document is still missing its delimiter.
+pub fn unfinished(input: &str) -> bool {
+    let mut lines = input.lines();
+    while let Some(x) = next_line(&mut lines) {
+        if incomplete(&x) || read_heredocs(&x, &mut lines).is_err() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Reads the next logical line, joining physical lines while the text is
+/// [`incomplete`]. A trailing `\` and its newline are removed.
+pub fn next_line<'a>(lines: &mut impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut line = lines.next()?.to_string();
+    loop {
+        let (open, backslash) = open_state(&line);
+        if !open {
+            break;
+        }
+        match lines.next() {
+            Some(x) => {
+                if backslash {
+                    line.pop();
+                } else {
+                    line.push('\n');
+                }
+                line.push_str(x);
+            }
+            None => break,
+        }
+    }
+    Some(line)
+}
+
+/// A here-document whose body has been read, waiting for its command.
+pub struct HereDoc {
+    pub body: String,
+    /// Set when the delimiter was quoted (`<<'EOF'`), which disables
+    /// expansion of the body.
+    pub literal: bool,
+}
+
+/// Reads a here-document delimiter starting at `chars[start]`, removing any
+/// quotes. Returns the delimiter, whether it was quoted and the index of the
+/// first character after it.
+fn read_delimiter(chars: &[char], start: usize) -> (String, bool, usize) {
+    let mut delimiter = String::new();
+    let mut literal = false;
+    let mut quote: Option<char> = None;
+    let mut i = start;
+    while i < chars.len() {
+        let c = chars[i];
+        match (quote, c) {
+            (Some(q), _) if q == c => quote = None,
+            (Some(_), _) => delimiter.push(c),
+            (None, '\\') => {
+                literal = true;
+                i += 1;
+                if let Some(next) = chars.get(i) {
+                    delimiter.push(*next);
+                }
+            }
+            (None, '\'') | (None, '"') => {
+                literal = true;
+                quote = Some(c);
+            }
+            (None, _) if c.is_whitespace() || ";&|<>()".contains(c) => break,
+            (None, _) => delimiter.push(c),
+        }
+        i += 1;
+    }
+    (delimiter, literal, i)
+}
+
+/// Finds the here-documents opened on a line, in order, as their delimiter,
+/// whether the `<<-` form (which strips leading tabs) was used and whether
+/// the delimiter was quoted.
+pub fn heredoc_delimiters(line: &str) -> Vec<(String, bool, bool)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut found = vec![];
+    let mut quote: Option<char> = None;
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match (quote, c) {
+            (Some(q), _) if q == c => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => i += 1,
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (_, '$') if chars.get(i + 1) == Some(&'(') && chars.get(i + 2) == Some(&'(') => {
+                depth += 2;
+                i += 3;
+                continue;
+            }
+            (_, '(') if depth > 0 => depth += 1,
+            (_, ')') if depth > 0 => depth -= 1,
+            (None, '#') if depth == 0 && (i == 0 || chars[i - 1].is_whitespace()) => break,
+            (None, '<') if depth == 0 && chars.get(i + 1) == Some(&'<') => {
+                if chars.get(i + 2) == Some(&'<') {
+                    i += 3;
+                    continue;
+                }
+                i += 2;
+                let strip_tabs = chars.get(i) == Some(&'-');
+                if strip_tabs {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i].is_whitespace() {
+                    i += 1;
+                }
+                let (delimiter, literal, next) = read_delimiter(&chars, i);
+                if !delimiter.is_empty() {
+                    found.push((delimiter, strip_tabs, literal));
+                }
+                i = next;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    found
+}
+
+/// Reads the bodies of the here-documents opened on `line` from the lines
+/// that follow it. Fails with the delimiter that was never found if the
+/// input ends first, together with what was read so far.
+pub fn read_heredocs<'a>(line: &str, lines: &mut impl Iterator<Item = &'a str>) -> Result<VecDeque<HereDoc>, (String, VecDeque<HereDoc>)> {
+    let mut heredocs = VecDeque::new();
+    for (delimiter, strip_tabs, literal) in heredoc_delimiters(line) {
+        let mut body = String::new();
+        let mut closed = false;
+        for x in lines.by_ref() {
+            let x = if strip_tabs { x.trim_start_matches('\t') } else { x };
+            if x.trim_end_matches('\r') == delimiter {
+                closed = true;
+                break;
+            }
+            body.push_str(x);
+            body.push('\n');
+        }
+        heredocs.push_back(HereDoc { body, literal });
+        if !closed {
+            return Err((delimiter, heredocs));
+        }
+    }
+    Ok(heredocs)
+}
+
+/// Expands `$` and backslash escapes in the body of a here-document.
+/// Quotes are kept as they are.
+pub fn expand_text(text: &str, status: i32) -> Result<String, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if matches!(chars.get(i + 1), Some('$') | Some('\\') | Some('`')) => {
+                out.push(chars[i + 1]);
+                i += 2;
+            }
+            '\\' if chars.get(i + 1) == Some(&'\n') => i += 2,
+            '$' => i = expand_dollar(&chars, i, status, &mut out)?,
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Splits a single command into words, removing quotes and expanding
+/// `$VAR`, `${VAR}`, `$?`, `$$` and `$(( ... ))` outside single quotes, and
+/// a `~` or `~/` that starts an unquoted word to the home directory.
+/// Here-strings (`<<<word`) and here-documents (`<<EOF`, taken in order from
+/// `heredocs`) become the text for the command's stdin, returned alongside
+/// the words.
+pub fn split_words(command: &str, status: i32, heredocs: &mut VecDeque<HereDoc>) -> Result<(Vec<String>, Option<String>), String> {
+    let chars: Vec<char> = command.chars().collect();
+    let mut words = vec![];
+    let mut stdin = None;
+    let mut current = String::new();
+    // Distinguishes an empty quoted word (`""`) from no word at all.
+    let mut in_word = false;
+    let mut double = false;
+    // Set after `<<<`, the next word is the here-string.
+    let mut herestring = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\\' {
+            i += 1;
+            if let Some(next) = chars.get(i) {
+                if double && !matches!(next, '$' | '\\' | '"') {
+                    current.push('\\');
+                }
+                current.push(*next);
+            }
+            in_word = true;
+        } else if c == '"' {
+            double = !double;
+            in_word = true;
+        } else if c == '\'' && !double {
+            let end = chars[i + 1..].iter().position(|x| *x == '\'').ok_or_else(|| "unterminated quote".to_string())?;
+            current.extend(&chars[i + 1..i + 1 + end]);
+            i += end + 1;
+            in_word = true;
+        } else if c == '$' {
+            i = expand_dollar(&chars, i, status, &mut current)?;
+            in_word = true;
+            continue;
+        } else if c == '<' && !double && chars.get(i + 1) == Some(&'<') {
+            if in_word {
+                push_word(&mut words, &mut stdin, &mut herestring, &mut current);
+                in_word = false;
+            }
+            if chars.get(i + 2) == Some(&'<') {
+                herestring = true;
+                i += 3;
+                continue;
+            }
+            i += 2;
+            if chars.get(i) == Some(&'-') {
+                i += 1;
+            }
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
+            }
+            let (delimiter, _, next) = read_delimiter(&chars, i);
+            if delimiter.is_empty() {
+                return Err("syntax error near `<<`".to_string());
+            }
+            let heredoc = heredocs.pop_front().ok_or_else(|| format!("missing here-document for `{}`", delimiter))?;
+            stdin = Some(if heredoc.literal { heredoc.body } else { expand_text(&heredoc.body, status)? });
+            i = next;
+            continue;
+        } else if c.is_whitespace() && !double {
+            if in_word {
+                push_word(&mut words, &mut stdin, &mut herestring, &mut current);
+                in_word = false;
+            }
+        } else if c == '~' && !in_word && !chars.get(i + 1).is_some_and(|x| *x != '/' && !x.is_whitespace()) {
+            match home_dir() {
+                Some(home) => current.push_str(&home.to_string_lossy()),
+                None => current.push(c),
+            }
+            in_word = true;
+        } else {
+            current.push(c);
+            in_word = true;
+        }
+        i += 1;
+    }
+    if double {
+        return Err("unterminated quote".to_string());
+    }
+    if in_word {
+        push_word(&mut words, &mut stdin, &mut herestring, &mut current);
+    }
+    if herestring {
+        return Err("syntax error near `<<<`".to_string());
+    }
+    Ok((words, stdin))
+}
+
+/// How many words at the start of the command are variable assignments:
+/// `NAME=value`, with the name and `=` neither quoted, escaped nor expanded.
+pub fn assignment_words(command: &str) -> usize {
+    let chars: Vec<char> = command.chars().collect();
+    let mut count = 0;
+    let mut i = 0;
+    loop {
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        let start = i;
+        while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+            i += 1;
+        }
+        if i == start || chars[start].is_numeric() || chars.get(i) != Some(&'=') {
+            return count;
+        }
+        count += 1;
+        // the value runs to the end of the word, as `split_words` reads it
+        let mut quote: Option<char> = None;
+        let mut depth = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match (quote, c) {
+                (Some(q), _) if q == c => quote = None,
+                (Some('\''), _) => {}
+                (_, '\\') => i += 1,
+                (None, '\'') | (None, '"') => quote = Some(c),
+                (_, '$') if chars.get(i + 1) == Some(&'(') && chars.get(i + 2) == Some(&'(') => {
+                    depth += 2;
+                    i += 2;
+                }
+                (_, '(') if depth > 0 => depth += 1,
+                (_, ')') if depth > 0 => depth -= 1,
+                (None, _) if depth == 0 && c.is_whitespace() => break,
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Ends the current word, which is the text of a pending here-string or
+/// else a new word.
+fn push_word(words: &mut Vec<String>, stdin: &mut Option<String>, herestring: &mut bool, current: &mut String) {
+    if *herestring {
+        *stdin = Some(format!("{}\n", current));
+        *herestring = false;
+    } else {
+        words.push(current.clone());
+    }
+    current.clear();
+}
+
+/// Expands the `$` at `chars[start]` into `out`, returning the index of the
+/// first character after the expansion.
+fn expand_dollar(chars: &[char], start: usize, status: i32, out: &mut String) -> Result<usize, String> {
+    let mut i = start + 1;
+    match chars.get(i) {
+        Some('(') if chars.get(i + 1) == Some(&'(') => {
+            let body_start = i + 2;
+            let mut depth = 2;
+            let mut j = body_start;
+            while j < chars.len() {
+                match chars[j] {
+                    '(' => depth += 1,
+                    ')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                j += 1;
+            }
+            if depth != 0 {
+                return Err("unterminated `$((`".to_string());
+            }
+            let expansion: String = chars[start..=j].iter().collect();
+            out.push_str(&arith::expand_arithmetic(&expansion)?);
+            Ok(j + 1)
+        }
+        Some('?') => {
+            out.push_str(&status.to_string());
+            Ok(i + 1)
+        }
+        Some('$') => {
+            out.push_str(&process::id().to_string());
+            Ok(i + 1)
+        }
+        Some('{') => {
+            let end = chars[i..].iter().position(|x| *x == '}').ok_or_else(|| "missing `}` in `${`".to_string())?;
+            let name: String = chars[i + 1..i + end].iter().collect();
+            out.push_str(&var(name).unwrap_or_default());
+            Ok(i + end + 1)
+        }
+        Some(x) if x.is_alphanumeric() || *x == '_' => {
+            let name_start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let name: String = chars[name_start..i].iter().collect();
+            out.push_str(&var(name).unwrap_or_default());
+            Ok(i)
+        }
+        _ => {
+            out.push('$');
+            Ok(i)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::env::set_var;
+    use super::*;
+
+    fn commands(line: &str) -> Vec<(Connector, String)> {
+        split_commands(line).unwrap()
+    }
+
+    fn words(command: &str) -> Vec<String> {
+        split_words(command, 0, &mut VecDeque::new()).unwrap().0
+    }
+
+    #[test]
+    fn command_lists() {
+        assert_eq!(commands("a; b && c || d"), vec![
+            (Connector::Always, "a".to_string()),
+            (Connector::Always, "b".to_string()),
+            (Connector::And, "c".to_string()),
+            (Connector::Or, "d".to_string()),
+        ]);
+        assert_eq!(commands("a;"), vec![(Connector::Always, "a".to_string())]);
+        assert_eq!(commands("echo a # b; c"), vec![(Connector::Always, "echo a".to_string())]);
+        assert_eq!(commands("echo a#b"), vec![(Connector::Always, "echo a#b".to_string())]);
+        assert!(commands("").is_empty());
+    }
+
+    #[test]
+    fn command_list_quoting() {
+        assert_eq!(commands("echo 'a; b' \"c && d\""), vec![(Connector::Always, "echo 'a; b' \"c && d\"".to_string())]);
+        assert_eq!(commands("echo a\\; b"), vec![(Connector::Always, "echo a\\; b".to_string())]);
+        assert_eq!(commands("echo $(( 1 || 0 )); b"), vec![
+            (Connector::Always, "echo $(( 1 || 0 ))".to_string()),
+            (Connector::Always, "b".to_string()),
+        ]);
+        assert_eq!(commands("echo '#' # comment"), vec![(Connector::Always, "echo '#'".to_string())]);
+    }
+
+    #[test]
+    fn command_list_errors() {
+        assert!(split_commands("&& a").is_err());
+        assert!(split_commands("a ;; b").is_err());
+        assert!(split_commands("a &&").is_err());
+        assert!(split_commands("echo 'a").is_err());
+        assert!(split_commands("echo $((1 + 2)").is_err());
+    }
+
+    #[test]
+    fn short_circuit() {
+        assert!(!Connector::Always.skips(0));
+        assert!(!Connector::Always.skips(1));
+        assert!(!Connector::And.skips(0));
+        assert!(Connector::And.skips(1));
+        assert!(Connector::Or.skips(0));
+        assert!(!Connector::Or.skips(2));
+    }
+
+    #[test]
+    fn word_quoting() {
+        assert_eq!(words("echo  a   b"), vec!["echo", "a", "b"]);
+        assert_eq!(words("echo 'a  b' \"c d\""), vec!["echo", "a  b", "c d"]);
+        assert_eq!(words("echo '' \"\""), vec!["echo", "", ""]);
+        assert_eq!(words("echo a'b'\"c\""), vec!["echo", "abc"]);
+        assert_eq!(words("echo '\"' \"'\""), vec!["echo", "\"", "'"]);
+        assert!(split_words("echo 'a", 0, &mut VecDeque::new()).is_err());
+        assert!(split_words("echo \"a", 0, &mut VecDeque::new()).is_err());
+    }
+
+    #[test]
+    fn word_escapes() {
+        assert_eq!(words("echo a\\ b"), vec!["echo", "a b"]);
+        assert_eq!(words("echo \\'a\\'"), vec!["echo", "'a'"]);
+        assert_eq!(words("echo \"a\\\"b\" \"\\$x\" \"\\n\""), vec!["echo", "a\"b", "$x", "\\n"]);
+        assert_eq!(words("echo '\\n'"), vec!["echo", "\\n"]);
+    }
+
+    #[test]
+    fn expansion() {
+        set_var("COSH_TEST_PARSE", "x y");
+        assert_eq!(words("echo $COSH_TEST_PARSE"), vec!["echo", "x y"]);
+        assert_eq!(words("echo ${COSH_TEST_PARSE}z"), vec!["echo", "x yz"]);
+        assert_eq!(words("echo '$COSH_TEST_PARSE'"), vec!["echo", "$COSH_TEST_PARSE"]);
+        assert_eq!(words("echo \"$COSH_TEST_PARSE\""), vec!["echo", "x y"]);
+        assert_eq!(words("echo $COSH_TEST_PARSE_UNSET."), vec!["echo", "."]);
+        assert_eq!(words("echo $ a$"), vec!["echo", "$", "a$"]);
+        assert_eq!(split_words("echo $?", 3, &mut VecDeque::new()).unwrap().0, vec!["echo", "3"]);
+        assert_eq!(words("echo $((2 * (3 + 1)))"), vec!["echo", "8"]);
+        assert!(split_words("echo ${COSH_TEST_PARSE", 0, &mut VecDeque::new()).is_err());
+    }
+
+    #[test]
+    fn tilde() {
+        let home = home_dir().unwrap().to_string_lossy().to_string();
+        assert_eq!(words("cd ~"), vec!["cd".to_string(), home.clone()]);
+        assert_eq!(words("ls ~/src"), vec!["ls".to_string(), format!("{}/src", home)]);
+        assert_eq!(words("echo '~' \\~ \"~/\" a~ ~user"), vec!["echo", "~", "~", "~/", "a~", "~user"]);
+    }
+
+    #[test]
+    fn unfinished_input() {
+        for x in ["echo 'a", "echo \"a", "echo a \\", "a &&", "a ||", "a && # then", "echo $(( 1 +", "echo $(( (1 +", "cat <<EOF", "cat <<EOF\nbody"] {
+            assert!(unfinished(x), "{:?} should continue", x);
+        }
+        for x in ["", "echo :(", "echo {", "find . -name {", "echo }", "grep a|", "a |", "a &", "echo '&&'", "echo a # &&", "echo \\&&", "cat <<EOF\nbody\nEOF", "echo $(( 1 + 2 ))", "echo $(ls"] {
+            assert!(!unfinished(x), "{:?} should run", x);
+        }
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(assignment_words("A=1 B='x y' C=\"$((1 + 2))\" echo"), 3);
+        assert_eq!(assignment_words("A=$(( 1 + 2 ))"), 1);
+        assert_eq!(assignment_words("_a1= b"), 1);
+        assert_eq!(assignment_words("\"A=1\""), 0);
+        assert_eq!(assignment_words("'A'=1"), 0);
+        assert_eq!(assignment_words("A\\=1"), 0);
+        assert_eq!(assignment_words("$X"), 0);
+        assert_eq!(assignment_words("1A=2"), 0);
+        assert_eq!(assignment_words("=1"), 0);
+        assert_eq!(assignment_words("echo A=1"), 0);
+    }
+
+    #[test]
+    fn here_documents() {
+        let (words, stdin) = split_words("cat <<< 'a b'", 0, &mut VecDeque::new()).unwrap();
+        assert_eq!((words, stdin), (vec!["cat".to_string()], Some("a b\n".to_string())));
+        let mut heredocs = VecDeque::from([HereDoc { body: "$((1 + 1))\n".to_string(), literal: false }]);
+        let (words, stdin) = split_words("cat <<EOF", 0, &mut heredocs).unwrap();
+        assert_eq!((words, stdin), (vec!["cat".to_string()], Some("2\n".to_string())));
+        let mut heredocs = VecDeque::from([HereDoc { body: "$((1 + 1))\n".to_string(), literal: true }]);
+        assert_eq!(split_words("cat <<'EOF'", 0, &mut heredocs).unwrap().1, Some("$((1 + 1))\n".to_string()));
+        assert_eq!(heredoc_delimiters("cat <<-'A' <<B # <<C"), vec![
+            ("A".to_string(), true, true),
+            ("B".to_string(), false, false),
+        ]);
+    }
+}
@@ -47,4 +47,21 @@ pub fn is_elevated() -> bool {
     use nix::unistd::Uid;
     Uid::effective().is_root()
 
-}
\ No newline at end of file
+}
+
+/// Effective user id, cheap enough to check before every prompt.
+#[cfg(unix)]
+pub fn effective_uid() -> Option<u32> {
+
+    use nix::unistd::Uid;
+    Some(Uid::effective().as_raw())
+
+}
+
+/// Windows has no user id to watch; `rehash` looks the identity up again.
+#[cfg(windows)]
+pub fn effective_uid() -> Option<u32> {
+
+    None
+
+}
@@ -1,11 +1,15 @@
 use std::borrow::Cow;
 use std::env::current_dir;
-use std::path::PathBuf;
+use std::time::Duration;
+use chrono::Local;
 use dirs::home_dir;
 use reedline::{Prompt, PromptEditMode, PromptHistorySearch, PromptHistorySearchStatus};
-use whoami::{hostname, username};
-use yansi::Paint;
-use crate::permission::is_elevated;
+use whoami::username;
+use yansi::{Color, Paint, Style};
+use crate::builtin::working_dir;
+use crate::config::DEFAULT_PROMPT;
+use crate::git::{GitStatus, status};
+use crate::permission::{effective_uid, is_elevated};
 
 /// Prints in stderr() with ANSI and a newline.
 pub fn err_ln(msg: String) {
@@ -20,27 +24,37 @@ pub fn err(msg: &str) {
 
 impl Prompt for Cosh {
 
-    fn render_prompt(&self, _screen_width: usize) -> Cow<str> {
-        let x = current_dir().unwrap().to_string_lossy().replace("\\", "/").replace(&home_dir().unwrap_or(PathBuf::new()).to_string_lossy().to_string(), "~").to_string();
-        Cow::from(format!("{} {}", Paint::yellow(x), Paint::green(username() + "@" + &*hostname())))
+    fn render_prompt(&self, screen_width: usize) -> Cow<'_, str> {
+        let left = trim_visible_end(&self.render_format(&self.format));
+        if self.right_format.is_empty() {
+            return Cow::from(left);
+        }
+        // like reedline's default prompt, the right side fills the rest of
+        // the line, and only shows if there is enough room for it
+        let right = self.render_format(&self.right_format);
+        let used = visible_width(&left) + visible_width(&right);
+        if visible_width(&right) == 0 || used + 1 > screen_width {
+            return Cow::from(left);
+        }
+        Cow::from(format!("{}{}{}", left, " ".repeat(screen_width - used), right))
     }
 
-    fn render_prompt_indicator(&self, _edit_mode: PromptEditMode) -> Cow<str> {
-        if is_elevated() {
+    fn render_prompt_indicator(&self, _edit_mode: PromptEditMode) -> Cow<'_, str> {
+        if self.elevated {
             " # ".into()
         } else {
             " $ ".into()
         }
     }
 
-    fn render_prompt_multiline_indicator(&self) -> Cow<str> {
+    fn render_prompt_multiline_indicator(&self) -> Cow<'_, str> {
         Cow::Borrowed("... ")
     }
 
     fn render_prompt_history_search_indicator(
         &self,
         history_search: PromptHistorySearch,
-    ) -> Cow<str> {
+    ) -> Cow<'_, str> {
         let prefix = match history_search.status {
             PromptHistorySearchStatus::Passing => "",
             PromptHistorySearchStatus::Failing => "failing ",
@@ -52,14 +66,234 @@ impl Prompt for Cosh {
     }
 }
 
+/// The host name, or `localhost` if it cannot be read.
+fn host_name() -> String {
+    whoami::fallible::hostname().unwrap_or_else(|_| "localhost".to_string())
+}
+
 impl Default for Cosh {
     fn default() -> Self {
-        Cosh {}
+        Cosh {
+            format: DEFAULT_PROMPT.to_string(),
+            right_format: String::new(),
+            status: 0,
+            git_dirty: false,
+            git: None,
+            duration: None,
+            duration_threshold: Duration::from_secs(2),
+            user: username(),
+            host: host_name(),
+            elevated: is_elevated(),
+            uid: effective_uid(),
+        }
     }
 }
 
+/// The cosh prompt, rendered from the `prompt` and `right_prompt` formats.
 #[derive(Clone)]
-pub struct Cosh {}
+pub struct Cosh {
+    pub format: String,
+    pub right_format: String,
+    /// Exit status of the last command.
+    pub status: i32,
+    /// Whether `{git}` checks for changed files.
+    pub git_dirty: bool,
+    /// The repository status, read by [`Cosh::refresh`] rather than on
+    /// every repaint.
+    pub git: Option<GitStatus>,
+    /// How long the last input took to run.
+    pub duration: Option<Duration>,
+    /// `{duration}` stays empty for inputs quicker than this.
+    pub duration_threshold: Duration,
+    // identity is looked up once, as checking elevation on Windows opens
+    // the process token; see `Cosh::refresh_identity`.
+    pub user: String,
+    pub host: String,
+    pub elevated: bool,
+    /// Effective user id at the last lookup, where there is one.
+    pub uid: Option<u32>,
+}
+
+impl Cosh {
+    /// Looks up the user, host and elevation again, as `rehash` asks.
+    pub fn refresh_identity(&mut self) {
+        self.user = username();
+        self.host = host_name();
+        self.elevated = is_elevated();
+        self.uid = effective_uid();
+    }
+
+    /// Updates what is expensive to compute, once before each prompt.
+    pub fn refresh(&mut self) {
+        // only a change of effective user (setuid) can change the identity
+        // of a running shell, and that is cheap to check
+        if effective_uid() != self.uid {
+            self.refresh_identity();
+        }
+        let uses_git = |x: &str| x.contains("{git");
+        self.git = if uses_git(&self.format) || uses_git(&self.right_format) {
+            current_dir().ok().and_then(|x| status(&x, self.git_dirty))
+        } else {
+            None
+        };
+    }
+
+    /// Replaces the placeholders of a prompt format:
+    /// - `{cwd}` - the working directory, with the home directory as `~`
+    /// - `{short_cwd}` - the last component of the working directory
+    /// - `{user}`, `{host}` - the user name and host name
+    /// - `{time}` - the local time, as `HH:MM:SS`
+    /// - `{status}` - the exit status of the last command
+    /// - `{duration}` - how long the last command took, empty if it was
+    ///   quicker than `duration_threshold`
+    /// - `{git_branch}` - the current git branch (or `@commit` when detached),
+    ///   empty outside a repository
+    /// - `{git}` - like `{git_branch}`, followed by `*` if tracked files were
+    ///   changed and `git_dirty` is enabled
+    /// - `{black}`, `{red}`, `{green}`, `{yellow}`, `{blue}`, `{magenta}`,
+    ///   `{cyan}`, `{white}`, `{bold}` and `{reset}` - colors and styles
+    ///
+    /// Unknown placeholders are kept as they are.
+    pub fn render_format(&self, format: &str) -> String {
+        let mut out = String::new();
+        let mut rest = format;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(x) => start + x,
+                None => break,
+            };
+            let name = &rest[start + 1..end];
+            match self.placeholder(name) {
+                Some(value) => out.push_str(&value),
+                None => out.push_str(&rest[start..=end]),
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn placeholder(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "cwd" => display_cwd(),
+            "short_cwd" => {
+                let cwd = display_cwd();
+                cwd.rsplit('/').find(|x| !x.is_empty()).unwrap_or(&cwd).to_string()
+            }
+            "user" => self.user.clone(),
+            "host" => self.host.clone(),
+            "time" => Local::now().format("%H:%M:%S").to_string(),
+            "status" => self.status.to_string(),
+            "duration" => match self.duration {
+                Some(x) if x >= self.duration_threshold => format_duration(x),
+                _ => String::new(),
+            },
+            "git_branch" => self.git.as_ref().map(|x| x.head.clone()).unwrap_or_default(),
+            "git" => match &self.git {
+                Some(x) if x.dirty => format!("{}*", x.head),
+                Some(x) => x.head.clone(),
+                None => String::new(),
+            },
+            "black" => style_start(Style::new(Color::Black)),
+            "red" => style_start(Style::new(Color::Red)),
+            "green" => style_start(Style::new(Color::Green)),
+            "yellow" => style_start(Style::new(Color::Yellow)),
+            "blue" => style_start(Style::new(Color::Blue)),
+            "magenta" => style_start(Style::new(Color::Magenta)),
+            "cyan" => style_start(Style::new(Color::Cyan)),
+            "white" => style_start(Style::new(Color::White)),
+            "bold" => style_start(Style::default().bold()),
+            "reset" => style_end(),
+            _ => return None,
+        })
+    }
+}
+
+/// The escape sequence that turns on a style for the text after it, empty
+/// when painting is disabled.
+fn style_start(style: Style) -> String {
+    let mut out = String::new();
+    if Paint::is_enabled() {
+        let _ = style.fmt_prefix(&mut out);
+    }
+    out
+}
+
+/// The escape sequence that turns every style off again.
+fn style_end() -> String {
+    let mut out = String::new();
+    if Paint::is_enabled() {
+        // the suffix of any style resets them all
+        let _ = Style::default().bold().fmt_suffix(&mut out);
+    }
+    out
+}
+
+/// The working directory with `/` separators and the home directory as `~`,
+/// marked if it has been deleted.
+fn display_cwd() -> String {
+    let (cwd, gone) = working_dir();
+    let x = cwd.to_string_lossy().replace("\\", "/").replace(&home_dir().unwrap_or_default().to_string_lossy().replace("\\", "/"), "~");
+    if gone {
+        format!("{} (deleted)", x)
+    } else {
+        x
+    }
+}
+
+/// Formats a duration for people, such as `850ms`, `3.2s` or `1m05s`.
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    let secs = duration.as_secs();
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else if secs < 60 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// Width of text on the terminal, leaving out ANSI escape sequences.
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut escape = false;
+    for c in text.chars() {
+        if escape {
+            escape = !c.is_ascii_alphabetic();
+        } else if c == '\x1b' {
+            escape = true;
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// The text without spaces at its visible end, keeping the escape sequences
+/// after them, so that a placeholder left empty at the end of the prompt
+/// leaves no gap before ` $ `.
+fn trim_visible_end(text: &str) -> String {
+    let mut rest = text;
+    let mut escapes = String::new();
+    loop {
+        if let Some(x) = rest.strip_suffix(' ') {
+            rest = x;
+            continue;
+        }
+        match rest.rfind('\x1b') {
+            Some(at) if rest[at..].chars().filter(char::is_ascii_alphabetic).count() == 1 && rest.ends_with('m') => {
+                escapes.insert_str(0, &rest[at..]);
+                rest = &rest[..at];
+            }
+            _ => break,
+        }
+    }
+    format!("{}{}", rest, escapes)
+}
 
 pub fn print_help() {
     println!(
@@ -90,13 +324,99 @@ Help ......................................
     cls      - clears the screen. This works like
              | (CTRL+L).
 
-    history  - prints the current history.
-             | `history clear` clears the
-             | history.
+    history [n]
+             - prints the (last n) commands
+             | with their numbers.
+             | `history search <text>`
+             | prints those containing the
+             | text, `history delete <n>`
+             | removes one and `history
+             | clear` all of them. `!!`
+             | runs the last command again,
+             | `!n` number n and `!text`
+             | the last starting with text.
+             | `history off` stops recording
+             | commands until `history on`.
+
+    let <expr...>
+             - evaluates each integer
+             | expression, e.g. `let i+=1`.
+             | Variables are assigned in
+             | the environment.
+
+    expr <expr>
+             - prints the value of the given
+             | integer expression.
+
+    test <expr> / [ <expr> ]
+             - checks files (-e, -f, -d,
+             | -x, -s, -r, -w, -L), strings
+             | (-z, -n, =, !=) and numbers
+             | (-eq, -ne, -lt, -le, -gt,
+             | -ge). `!`, `-a` and `-o`
+             | combine checks.
+
+    source <file> / . <file>
+             - runs each line of the file
+             | in the current session.
+             | `coshrc` in the config
+             | directory is sourced when
+             | cosh starts.
+
+    export [name=value...]
+             - sets environment variables,
+             | or lists them if no argument
+             | is given. `name=value` alone
+             | works as well, and before a
+             | command sets it only for
+             | that command.
+
+    unset <name...>
+             - removes environment variables.
+             | There are no functions to
+             | remove with `-f`.
+
+    alias [name[=value]...]
+             - defines aliases, or prints
+             | them. `alias --save` writes
+             | the current aliases to
+             | cosh.toml.
+
+    unalias <name...>
+             - removes aliases. `-a` removes
+             | all of them.
+
+    complete [options] <command> [sub...]
+             - teaches tab completion the
+             | subcommands (-s), flags (-f),
+             | words (-w) and argument kind
+             | (-a path|dir|command|none)
+             | of a command. `complete -r`
+             | removes a spec, and no
+             | arguments prints them all.
+
+    type [-a] <name...> / which [-a] <name...>
+             - tells whether each name runs
+             | an alias, a builtin or an
+             | executable, and which one.
+             | `-a` lists every match.
+             | `command -v` prints only the
+             | path, and `command <name>`
+             | runs it skipping aliases.
+
+    time <command>
+             - runs the command, then
+             | prints how long it took.
+
+    rehash   - reads the commands in PATH
+             | and the user and host name
+             | for the prompt again.
 
     echo     - echoes the given arguments.
     pwd      - prints the current working dir.
-    exit     - exits cosh.
+    exit [n] - exits cosh with status n
+             | (0-255), or that of the last
+             | command.
 
 --------------------------------------------
 "#;
\ No newline at end of file
@@ -0,0 +1,605 @@
+use std::collections::HashMap;
+use std::env::{remove_var, set_var, var_os, vars};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use clearscreen::clear;
+use reedline::Reedline;
+use crate::builtin::*;
+use crate::parse::{HereDoc, assignment_words, next_line, read_heredocs, split_commands, split_words};
+use std::collections::VecDeque;
+use crate::{arith, execute_command};
+use crate::config::{Configuration, save_aliases};
+use crate::completer::CommandIndex;
+use crate::compspec::{CompletionSpecs, complete};
+use crate::history::HistoryLog;
+use crate::panics::catch_fault;
+use crate::print::*;
+
+/// Something a command name can run, see [`Shell::resolve`].
+enum Resolution {
+    Alias(String),
+    Builtin,
+    File(PathBuf),
+}
+
+/// The state of a running cosh session, and the builtin dispatcher.
+pub struct Shell {
+    pub rl: Reedline,
+    /// Exit status of the last command, available as `$?`.
+    pub status: i32,
+    /// Set by `exit`, the REPL stops once this is true.
+    pub exiting: bool,
+    /// Set by `rehash`, the REPL looks up the user and host again.
+    pub rehash: bool,
+    /// Path of the history file.
+    pub history_str: String,
+    /// Command names for completion, also used by the hinter.
+    pub commands: CommandIndex,
+    /// Completion specs, changed by `complete`.
+    pub specs: CompletionSpecs,
+    /// Commands run in this session, which hints are drawn from.
+    pub log: HistoryLog,
+    /// How many `source` calls are currently running, to stop a file that
+    /// sources itself.
+    pub source_depth: u32,
+    pub config: Configuration,
+    /// Aliases by name. These start out as the `macros` and `aliases` from
+    /// the config.
+    pub aliases: HashMap<String, String>,
+    /// Aliases being expanded right now, which are not expanded again.
+    pub expanding: Vec<String>,
+}
+
+/// Limit on nested `source` calls.
+const MAX_SOURCE_DEPTH: u32 = 64;
+
+/// Whether the word can name a variable.
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(|x| x.is_alphabetic() || x == '_')
+        && chars.all(|x| x.is_alphanumeric() || x == '_')
+}
+
+/// Splits `NAME=value`, if the word is a variable assignment.
+pub fn assignment(word: &str) -> Option<(&str, &str)> {
+    word.split_once('=').filter(|(name, _)| is_name(name))
+}
+
+/// Quotes a value so that it reads back as a single word.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+impl Shell {
+    /// Creates a session, taking its aliases from the config.
+    pub fn new(rl: Reedline, config: Configuration, history_str: String, commands: CommandIndex, specs: CompletionSpecs, log: HistoryLog) -> Shell {
+        let mut aliases: HashMap<String, String> = config.macros.iter()
+            .flatten()
+            .map(|(original, alias)| (alias.clone(), original.clone()))
+            .collect();
+        aliases.extend(config.aliases.clone());
+        Shell {
+            rl,
+            status: 0,
+            exiting: false,
+            rehash: false,
+            history_str,
+            commands,
+            specs,
+            log,
+            source_depth: 0,
+            config,
+            aliases,
+            expanding: vec![],
+        }
+    }
+
+    /// Runs input that may span several lines, such as a command followed by
+    /// the bodies of its here-documents, or a whole script.
+    pub fn run_input(&mut self, input: &str) {
+        let mut lines = input.lines();
+        while let Some(line) = next_line(&mut lines) {
+            if self.exiting {
+                break;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut heredocs = match read_heredocs(&line, &mut lines) {
+                Ok(x) => x,
+                Err((delimiter, x)) => {
+                    err_ln(format!("cosh: here-document ended by end of input (wanted `{}`)", delimiter));
+                    x
+                }
+            };
+            self.run_line(&line, &mut heredocs);
+        }
+    }
+
+    /// Runs a line of input, which may hold several commands joined by
+    /// `;`, `&&` and `||`. Here-documents opened on the line are taken from
+    /// `heredocs`, in order.
+    pub fn run_line(&mut self, line: &str, heredocs: &mut VecDeque<HereDoc>) {
+        let commands = match split_commands(line) {
+            Ok(x) => x,
+            Err(e) => {
+                err_ln(format!("cosh: {}", e));
+                self.status = 2;
+                return;
+            }
+        };
+        for (connector, command) in commands {
+            if self.exiting {
+                return;
+            }
+            if connector.skips(self.status) {
+                continue;
+            }
+            let (first, rest) = command.split_once(char::is_whitespace).unwrap_or((&command, ""));
+            if let Some(expansion) = self.aliases.get(first).filter(|_| !self.expanding.iter().any(|x| x == first)) {
+                let line = format!("{} {}", expansion, rest);
+                self.expanding.push(first.to_string());
+                self.run_line(&line, heredocs);
+                self.expanding.pop();
+                continue;
+            }
+            match split_words(&command, self.status, heredocs) {
+                // told apart before expansion, so `"A=1"` or a `$X` holding
+                // `A=1` is still a command
+                Ok((words, _)) if !words.is_empty() && assignment_words(&command) == words.len() => {
+                    for (name, value) in words.iter().filter_map(|x| assignment(x)) {
+                        set_var(name, value);
+                    }
+                    self.status = 0;
+                }
+                // before a command, they are only set while it runs
+                Ok((words, stdin)) => {
+                    let count = assignment_words(&command).min(words.len());
+                    let saved: Vec<_> = words[..count].iter()
+                        .filter_map(|x| assignment(x))
+                        .map(|(name, value)| {
+                            let old = var_os(name);
+                            set_var(name, value);
+                            (name, old)
+                        })
+                        .collect();
+                    self.status = self.run(&words[count..], stdin);
+                    for (name, old) in saved.into_iter().rev() {
+                        match old {
+                            Some(x) => set_var(name, x),
+                            None => remove_var(name),
+                        }
+                    }
+                }
+                Err(e) => {
+                    err_ln(format!("cosh: {}", e));
+                    self.status = 2;
+                }
+            }
+        }
+    }
+
+    /// Runs every line of a file in the current session, returning the exit
+    /// status of the last command.
+    pub fn source(&mut self, path: &Path) -> i32 {
+        if self.source_depth >= MAX_SOURCE_DEPTH {
+            err_ln(format!("cosh: source: {}: too many nested `source` calls", path.to_string_lossy()));
+            return 1;
+        }
+        let contents = match read_to_string(path) {
+            Ok(x) => x,
+            Err(e) => {
+                err_ln(format!("cosh: source: {}: {}", path.to_string_lossy(), e));
+                return 1;
+            }
+        };
+        self.source_depth += 1;
+        self.status = 0;
+        self.run_input(&contents);
+        self.source_depth -= 1;
+        self.status
+    }
+
+    /// Gives reedline a fresh copy of the latest commands, for when the
+    /// history changed other than by a line being entered.
+    pub fn reload_history(&mut self) {
+        let history = self.log.reedline_history(self.config.history_capacity as usize);
+        let rl = std::mem::replace(&mut self.rl, Reedline::create().unwrap());
+        self.rl = rl.with_history(history).unwrap();
+    }
+
+    /// The `history` builtin: `history [n]` prints the (last `n`) commands
+    /// with their numbers, `history search <text>` those containing the
+    /// text, `history delete <n>` removes one and `history clear` all.
+    /// `history off` stops recording commands, until `history on`.
+    fn history(&mut self, args: &[String]) -> i32 {
+        let entries = self.log.entries();
+        let print = |number: usize, command: &str| {
+            println!("{:>5}  {}", number, command.replace('\n', "\n       "));
+        };
+        match args.first().map(|x| x.as_str()) {
+            None => {
+                for (i, x) in entries.iter().enumerate() {
+                    print(i + 1, &x.command);
+                }
+                0
+            }
+            Some("search") => {
+                let text = args[1..].join(" ");
+                let mut status = 1;
+                for (i, x) in entries.iter().enumerate().filter(|x| x.1.command.contains(&text)) {
+                    print(i + 1, &x.command);
+                    status = 0;
+                }
+                status
+            }
+            Some("delete") => {
+                let number = match args.get(1).and_then(|x| x.parse::<usize>().ok()) {
+                    Some(x) => x,
+                    None => {
+                        err_ln("cosh: history: delete needs an entry number".to_string());
+                        return 2;
+                    }
+                };
+                match self.log.delete(number) {
+                    Ok(_) => {
+                        self.reload_history();
+                        0
+                    }
+                    Err(e) => {
+                        err_ln(format!("cosh: history: {}", e));
+                        1
+                    }
+                }
+            }
+            Some("off") => {
+                self.log.set_recording(false);
+                println!("cosh: history off - commands are not recorded until `history on`");
+                0
+            }
+            Some("on") => {
+                self.log.set_recording(true);
+                0
+            }
+            Some("clear") => {
+                match self.log.clear() {
+                    Ok(_) => {
+                        self.reload_history();
+                        println!("cosh: history file ({}) emptied", self.history_str);
+                        0
+                    }
+                    Err(e) => {
+                        err_ln(format!("cosh: could not empty ({})", self.history_str));
+                        err_ln(format!("cosh: error - {}", e));
+                        1
+                    }
+                }
+            }
+            Some(x) => match x.parse::<usize>() {
+                Ok(n) => {
+                    for (i, x) in entries.iter().enumerate().skip(entries.len().saturating_sub(n)) {
+                        print(i + 1, &x.command);
+                    }
+                    0
+                }
+                Err(_) => {
+                    err_ln(format!("cosh: history: {}: unknown option", x));
+                    2
+                }
+            },
+        }
+    }
+
+    /// Runs a single, already expanded command and returns its exit status.
+    /// `stdin` is the text of a here-document or here-string, which only
+    /// external commands read.
+    fn run(&mut self, words: &[String], stdin: Option<String>) -> i32 {
+        let (command, args) = match words.split_first() {
+            None => return self.status,
+            Some((command, args)) => (command.as_str(), args),
+        };
+        match catch_fault(|| self.run_command(command, args, stdin)) {
+            Ok(x) => x,
+            Err(e) => {
+                err_ln(format!("cosh: {}: internal error: {}", command, e));
+                1
+            }
+        }
+    }
+
+    /// Everything `name` can run, in the order it is looked up: an alias
+    /// first, then a builtin, then the executables in `PATH`. The first is
+    /// the one that runs.
+    fn resolve(&self, name: &str) -> Vec<Resolution> {
+        let mut found = vec![];
+        if let Some(x) = self.aliases.get(name) {
+            found.push(Resolution::Alias(x.clone()));
+        }
+        if is_builtin(name) {
+            found.push(Resolution::Builtin);
+        }
+        found.extend(find_executables(name).into_iter().map(Resolution::File));
+        found
+    }
+
+    /// The `type`, `which` and `command -v`/`-V` builtins, which tell what
+    /// each name runs, or with `-a` everything it could run.
+    fn describe(&self, command: &str, args: &[String]) -> i32 {
+        let all = args.first().is_some_and(|x| x == "-a");
+        let names = if all { &args[1..] } else { args };
+        let builtin = command.split(' ').next().unwrap_or(command);
+        if names.is_empty() {
+            let usage = match command {
+                "type" | "which" => format!("{} [-a] <name...>", command),
+                _ => format!("{} <name...>", command),
+            };
+            err_ln(format!("cosh: {}: usage: {}", builtin, usage));
+            return 2;
+        }
+        let mut status = 0;
+        for name in names {
+            let found = self.resolve(name);
+            if found.is_empty() {
+                // `command -v` only answers with its status
+                if command != "command -v" {
+                    err_ln(format!("cosh: {}: {}: not found", builtin, name));
+                }
+                status = 1;
+            }
+            for x in found.iter().take(if all { found.len() } else { 1 }) {
+                match (command, x) {
+                    ("which", Resolution::Alias(value)) => println!("{}: aliased to {}", name, value),
+                    ("which", Resolution::Builtin) => println!("{}: shell builtin", name),
+                    ("command -v", Resolution::Alias(value)) => println!("alias {}={}", name, quote(value)),
+                    ("command -v", Resolution::Builtin) => println!("{}", name),
+                    ("which" | "command -v", Resolution::File(path)) => println!("{}", path.display()),
+                    (_, Resolution::Alias(value)) => println!("{} is an alias for {}", name, value),
+                    (_, Resolution::Builtin) => println!("{} is a shell builtin", name),
+                    (_, Resolution::File(path)) => println!("{} is {}", name, path.display()),
+                }
+            }
+        }
+        status
+    }
+
+    /// Runs a builtin, or else an external command. What counts as a builtin
+    /// is up to [`is_builtin`], which `type` and completion go by as well.
+    fn run_command(&mut self, command: &str, args: &[String], stdin: Option<String>) -> i32 {
+        if !is_builtin(command) {
+            return execute_command(command, args, stdin);
+        }
+        match command {
+            "pwd" => {
+                let (cwd, gone) = working_dir();
+                if gone {
+                    err_ln("cosh: pwd: the working directory has been deleted".to_string());
+                }
+                println!("{}", cwd.to_string_lossy());
+                0
+            }
+            "history" => self.history(args),
+            "echo" => {
+                println!("{}", args.join(" "));
+                0
+            }
+            "let" => {
+                let mut last = 0;
+                for x in args {
+                    match arith::eval(x) {
+                        Ok(v) => last = v,
+                        Err(e) => {
+                            err_ln(format!("cosh: let: {}", e));
+                            return 2;
+                        }
+                    }
+                }
+                // like `sh`, a zero result is reported as failure
+                (last == 0) as i32
+            }
+            "expr" => {
+                match arith::eval(&args.join(" ")) {
+                    Ok(x) => {
+                        println!("{}", x);
+                        (x == 0) as i32
+                    }
+                    Err(e) => {
+                        err_ln(format!("cosh: expr: {}", e));
+                        2
+                    }
+                }
+            }
+            "source" | "." => {
+                match args.first() {
+                    Some(file) => self.source(Path::new(file)),
+                    None => {
+                        err_ln(format!("cosh: {}: filename argument required", command));
+                        2
+                    }
+                }
+            }
+            "export" => {
+                if args.is_empty() {
+                    for (name, value) in vars() {
+                        println!("{}={}", name, value);
+                    }
+                }
+                for x in args {
+                    if let Some((name, value)) = assignment(x) {
+                        set_var(name, value);
+                    } else if !is_name(x) {
+                        err_ln(format!("cosh: export: `{}` is not a valid name", x));
+                        return 1;
+                    }
+                }
+                0
+            }
+            "alias" => {
+                if args.is_empty() {
+                    let mut names: Vec<_> = self.aliases.keys().collect();
+                    names.sort();
+                    for name in names {
+                        println!("alias {}={}", name, quote(&self.aliases[name]));
+                    }
+                    return 0;
+                }
+                let mut status = 0;
+                for x in args {
+                    if x == "--save" {
+                        if let Err(e) = save_aliases(&self.aliases) {
+                            err_ln(format!("cosh: alias: could not save cosh.toml - {}", e));
+                            status = 1;
+                        }
+                    } else if let Some((name, value)) = x.split_once('=') {
+                        if name.is_empty() || name.contains(char::is_whitespace) {
+                            err_ln(format!("cosh: alias: `{}` is not a valid alias name", name));
+                            status = 1;
+                        } else {
+                            self.aliases.insert(name.to_string(), value.to_string());
+                        }
+                    } else if let Some(value) = self.aliases.get(x) {
+                        println!("alias {}={}", x, quote(value));
+                    } else {
+                        err_ln(format!("cosh: alias: {}: not found", x));
+                        status = 1;
+                    }
+                }
+                status
+            }
+            "unalias" => {
+                if args.first().map(|x| x.as_str()) == Some("-a") {
+                    self.aliases.clear();
+                    return 0;
+                }
+                let mut status = 0;
+                for x in args {
+                    if self.aliases.remove(x).is_none() {
+                        err_ln(format!("cosh: unalias: {}: not found", x));
+                        status = 1;
+                    }
+                }
+                status
+            }
+            "unset" => {
+                let mut status = 0;
+                for x in args {
+                    match x.as_str() {
+                        "-v" => {}
+                        // there are only variables to unset
+                        "-f" => {
+                            err_ln("cosh: unset: -f: cosh has no functions".to_string());
+                            return 2;
+                        }
+                        _ if is_name(x) => remove_var(x),
+                        _ => {
+                            err_ln(format!("cosh: unset: `{}`: not a valid identifier", x));
+                            status = 1;
+                        }
+                    }
+                }
+                status
+            }
+            "time" => {
+                let start = Instant::now();
+                let before = cpu_times();
+                let status = self.run(args, stdin);
+                let cpu = match (before, cpu_times()) {
+                    (Some(x), Some(y)) => Some((y.0.saturating_sub(x.0), y.1.saturating_sub(x.1))),
+                    _ => None,
+                };
+                print_times(start.elapsed(), cpu);
+                status
+            }
+            "complete" => complete(&self.specs, args),
+            "type" | "which" => self.describe(command, args),
+            "command" => match args.split_first() {
+                Some((flag, rest)) if flag == "-v" => self.describe("command -v", rest),
+                Some((flag, rest)) if flag == "-V" => self.describe("command -V", rest),
+                // runs the command itself, never an alias
+                Some((name, rest)) => self.run_command(name, rest, stdin),
+                None => 0,
+            },
+            "test" => test(args),
+            "[" => {
+                match args.split_last() {
+                    Some((last, rest)) if last == "]" => test(rest),
+                    _ => {
+                        err_ln("cosh: [: missing `]`".to_string());
+                        2
+                    }
+                }
+            }
+            "help" => {
+                print_help();
+                0
+            }
+            "cosh" => {
+                err_ln("cosh: if we let you do this, cosh would break :c".to_string());
+                1
+            }
+            "cd" => {
+                let new_dir = args.first().map_or("/", |x| x.as_str());
+                let root = Path::new(new_dir);
+                if let Err(e) = change_dir(root) {
+                    err_ln(format!("cosh: {}", e));
+                    return 1;
+                }
+                0
+            },
+            "cls" => {
+                clear().unwrap();
+                0
+            }
+            "ls" => {
+                let mut dir = String::new();
+                let mut proc_args = Vec::<String>::new();
+                for arg in args {
+                    if arg.starts_with('-') {
+                        if arg.starts_with("--") {
+                            err_ln("cosh: the internal `ls` implementation only recognizes short flags (-l, etc)".to_string());
+                            continue;
+                        } else {
+                            proc_args.push(arg.trim().to_owned());
+                        }
+                    } else if dir.is_empty() {
+                        dir = arg.trim().to_owned();
+                    } else {
+                        err_ln("cosh: `ls` expected one parameter".to_string());
+                        continue;
+                    }
+                }
+                ls(&dir, proc_args)
+            }
+            "rehash" => {
+                self.commands.rescan();
+                self.rehash = true;
+                0
+            }
+            "autocp" | "autocp-ref" => {
+                err_ln(format!("cosh: {}: deprecated, commands are now kept up to date on their own; use `rehash` to read them again", command));
+                self.commands.rescan();
+                0
+            }
+            "exit" => {
+                let status = match args {
+                    [] => self.status,
+                    // only the low 8 bits reach the parent, as in POSIX shells
+                    [x] => match x.parse::<i32>() {
+                        Ok(x) => x & 0xff,
+                        Err(_) => {
+                            err_ln(format!("cosh: exit: {}: numeric argument required", x));
+                            return 2;
+                        }
+                    },
+                    _ => {
+                        err_ln("cosh: exit: too many arguments".to_string());
+                        return 2;
+                    }
+                };
+                self.exiting = true;
+                status
+            }
+            command => unreachable!("`{}` is listed as a builtin, but does not run as one", command),
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use reedline::{ValidationResult, Validator};
+use crate::parse::unfinished;
+
+/// Asks for more lines while the input is unfinished, showing the
+/// multiline indicator of the prompt.
+pub struct CoshValidator;
+
+impl Validator for CoshValidator {
+    fn validate(&self, line: &str) -> ValidationResult {
+        // open quotes or `$((`, trailing `\`, `&&` or `||`, or a
+        // here-document still missing its delimiter
+        if unfinished(line) {
+            ValidationResult::Incomplete
+        } else {
+            ValidationResult::Complete
+        }
+    }
+}